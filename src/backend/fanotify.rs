@@ -0,0 +1,388 @@
+use libc::{ c_char, c_int, c_uint, };
+use std::collections::HashMap;
+use std::ffi::{ CStr, OsStr, };
+use std::fs::File;
+use std::io::{ Error, Read, };
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{ AsRawFd, FromRawFd, RawFd, };
+use std::path::{ Path, PathBuf, };
+use super::{ Backend, FsEvent, FsEventKind, };
+use super::super::watches::WatchStats;
+
+// The libc crate we depend on predates these, see fanotify(7).
+const FAN_CLASS_NOTIF: c_uint = 0x0000_0000;
+const FAN_CLOEXEC: c_uint = 0x0000_0001;
+const FAN_REPORT_DFID_NAME: c_uint = 0x0000_0c00;
+const FAN_MARK_ADD: c_uint = 0x0000_0001;
+const FAN_MARK_FILESYSTEM: c_uint = 0x0000_0100;
+
+const FAN_ATTRIB: u64 = 0x0000_0004;
+const FAN_CLOSE_WRITE: u64 = 0x0000_0008;
+const FAN_CREATE: u64 = 0x0000_0100;
+const FAN_MOVED_FROM: u64 = 0x0000_0040;
+const FAN_MOVED_TO: u64 = 0x0000_0080;
+const FAN_DELETE: u64 = 0x0000_0200;
+const FAN_Q_OVERFLOW: u64 = 0x0000_4000;
+const FAN_RENAME: u64 = 0x1000_0000;
+const FAN_ONDIR: u64 = 0x4000_0000;
+
+const FAN_EVENT_INFO_TYPE_DFID_NAME: u8 = 2;
+const FAN_EVENT_INFO_TYPE_OLD_DFID_NAME: u8 = 10;
+const FAN_EVENT_INFO_TYPE_NEW_DFID_NAME: u8 = 12;
+
+const BUFFER_SIZE: usize = 16384;
+
+extern "C" {
+    fn fanotify_init(flags: c_uint, event_f_flags: c_uint) -> c_int;
+    fn fanotify_mark(fd: c_int, flags: c_uint, mask: u64, dirfd: c_int, path: *const c_char) -> c_int;
+    fn open_by_handle_at(mount_fd: c_int, handle: *mut u32, flags: c_int) -> c_int;
+}
+
+/// `struct fanotify_event_metadata`
+#[allow(dead_code)]
+#[repr(C)]
+struct EventMetadata {
+    event_len: u32,
+    vers: u8,
+    reserved: u8,
+    metadata_len: u16,
+    mask: u64,
+    fd: i32,
+    pid: i32,
+}
+
+/// `struct fanotify_event_info_header`
+#[allow(dead_code)]
+#[repr(C)]
+struct InfoHeader {
+    info_type: u8,
+    pad: u8,
+    len: u16,
+}
+
+/// Identifies a filesystem, as reported by `statfs` and in each event.
+type FsId = [i32; 2];
+
+struct Root {
+    path: PathBuf,
+    canonical: PathBuf,
+}
+
+/// A directory handle and entry name, as found in an event's info records.
+struct Entry {
+    info_type: u8,
+    fsid: FsId,
+    /// `struct file_handle`, kept in `u32`s so it is aligned for the kernel.
+    handle: Vec<u32>,
+    name: PathBuf,
+}
+
+/// Watches whole filesystems with a single fanotify mark each, instead of a
+/// watch per directory, and keeps only events under the requested roots.
+///
+/// Needs `CAP_SYS_ADMIN` to mark a filesystem and `CAP_DAC_READ_SEARCH` to turn
+/// the directory handles in events back into paths. Before Linux 5.17 the two
+/// halves of a rename can't be matched up, so they are reported separately.
+pub struct FanotifyBackend {
+    fanotify: File,
+    /// What to mark filesystems for, without `FAN_RENAME` once the kernel refused it.
+    mask: u64,
+    /// A descriptor on each marked filesystem to resolve handles against.
+    mounts: HashMap<FsId, File>,
+    roots: Vec<Root>,
+    /// fanotify reports a rename as one event, this numbers the halves we split it into.
+    cookie: u32,
+    warnings: Vec<String>,
+    buffer: Vec<u8>,
+}
+
+impl FanotifyBackend {
+    /// Fails when fanotify isn't available or we lack the privileges to use it.
+    pub fn new() -> Result<FanotifyBackend, String> {
+        let fd = unsafe {
+            fanotify_init(FAN_CLASS_NOTIF | FAN_CLOEXEC | FAN_REPORT_DFID_NAME,
+                          (libc::O_RDONLY | libc::O_CLOEXEC | libc::O_LARGEFILE) as c_uint)
+        };
+        if fd < 0 {
+            return Err(format!("Error starting up fanotify: {}", Error::last_os_error()));
+        }
+
+        Ok(FanotifyBackend {
+            fanotify: unsafe { File::from_raw_fd(fd) },
+            mask: FAN_ATTRIB | FAN_CLOSE_WRITE | FAN_CREATE | FAN_DELETE | FAN_RENAME | FAN_ONDIR,
+            mounts: HashMap::new(),
+            roots: vec!(),
+            cookie: 0,
+            warnings: vec!(),
+            buffer: vec!(0u8; BUFFER_SIZE),
+        })
+    }
+
+    /// Mark the filesystem a root lives on, unless it is already marked.
+    ///
+    /// An unprivileged fanotify can be started but not marked with, so this is
+    /// worth trying before relying on the backend.
+    pub fn mark(&mut self, root: &Path) -> Result<(), String> {
+        let mount = File::open(root)
+            .map_err(|e| format!("Can't watch {}: {}", root.display(), e))?;
+        let fsid = fsid_of(&mount)
+            .map_err(|e| format!("Can't watch {}: {}", root.display(), e))?;
+        if self.mounts.contains_key(&fsid) {
+            return Ok(());
+        }
+
+        let path = std::ffi::CString::new(root.as_os_str().as_bytes())
+            .map_err(|e| format!("Can't watch {}: {}", root.display(), e))?;
+        let mut error = self.add_mark(&path);
+        if self.mask & FAN_RENAME != 0 && error.as_ref().and_then(Error::raw_os_error) == Some(libc::EINVAL) {
+            // Too old for FAN_RENAME, settle for the separate halves.
+            self.mask = (self.mask & !FAN_RENAME) | FAN_MOVED_FROM | FAN_MOVED_TO;
+            error = self.add_mark(&path);
+        }
+        if let Some(e) = error {
+            return Err(format!("Failed to add fanotify mark for {}: {}", root.display(), e));
+        }
+
+        self.mounts.insert(fsid, mount);
+        Ok(())
+    }
+
+    fn add_mark(&self, path: &CStr) -> Option<Error> {
+        let result = unsafe {
+            fanotify_mark(self.fanotify.as_raw_fd(), FAN_MARK_ADD | FAN_MARK_FILESYSTEM,
+                          self.mask, libc::AT_FDCWD, path.as_ptr())
+        };
+        if result < 0 { Some(Error::last_os_error()) } else { None }
+    }
+
+    /// Turn a directory handle and name back into a path under one of the roots.
+    fn resolve(&self, entry: &mut Entry) -> Option<PathBuf> {
+        let mount = self.mounts.get(&entry.fsid)?;
+        let fd = unsafe {
+            open_by_handle_at(mount.as_raw_fd(), entry.handle.as_mut_ptr(), libc::O_PATH)
+        };
+        if fd < 0 {
+            // Usually the directory is already gone.
+            return None;
+        }
+        let dir = unsafe { File::from_raw_fd(fd) };
+        let dir = std::fs::read_link(format!("/proc/self/fd/{}", dir.as_raw_fd())).ok()?;
+        let path = dir.join(&entry.name);
+
+        self.roots
+            .iter()
+            .filter(|root| path.starts_with(&root.canonical))
+            .max_by_key(|root| root.canonical.components().count())
+            .and_then(|root| path.strip_prefix(&root.canonical).ok().map(|rest| root.path.join(rest)))
+    }
+
+    /// Split one fanotify event into the events everyone else expects.
+    fn translate(&mut self, mask: u64, mut entries: Vec<Entry>) -> Vec<FsEvent> {
+        let is_dir = mask & FAN_ONDIR != 0;
+        let mut events = vec!();
+
+        if mask & FAN_RENAME != 0 {
+            self.cookie = self.cookie.wrapping_add(1);
+            for entry in &mut entries {
+                let kind = match entry.info_type {
+                    FAN_EVENT_INFO_TYPE_OLD_DFID_NAME => FsEventKind::MovedFrom(self.cookie),
+                    FAN_EVENT_INFO_TYPE_NEW_DFID_NAME => FsEventKind::MovedTo(self.cookie),
+                    _                                 => continue,
+                };
+                if let Some(path) = self.resolve(entry) {
+                    events.push(FsEvent::new(kind, path, is_dir));
+                }
+            }
+            return events;
+        }
+
+        let path = match entries.iter_mut()
+            .find(|entry| entry.info_type == FAN_EVENT_INFO_TYPE_DFID_NAME)
+            .and_then(|entry| self.resolve(entry)) {
+            Some(path) => path,
+            None       => return events,
+        };
+
+        // The kernel merges events on the same file, so report them in the order
+        // they most likely happened.
+        // Without FAN_RENAME each half gets a cookie of its own, so they stay unpaired.
+        if mask & (FAN_MOVED_FROM | FAN_MOVED_TO) != 0 {
+            self.cookie = self.cookie.wrapping_add(2);
+        }
+        let kinds = [
+            (FAN_MOVED_FROM,  FsEventKind::MovedFrom(self.cookie.wrapping_sub(1))),
+            (FAN_CREATE,      FsEventKind::Created),
+            (FAN_CLOSE_WRITE, FsEventKind::Modified),
+            (FAN_ATTRIB,      FsEventKind::Attrib),
+            (FAN_DELETE,      FsEventKind::Removed),
+            (FAN_MOVED_TO,    FsEventKind::MovedTo(self.cookie)),
+        ];
+        for (bit, kind) in kinds.iter() {
+            if mask & bit != 0 {
+                events.push(FsEvent::new(*kind, path.clone(), is_dir));
+            }
+        }
+        events
+    }
+
+    /// Every root has to be looked at again, there's no telling what changed.
+    fn overflowed(&mut self) -> Vec<FsEvent> {
+        self.warnings.push("fanotify queue overflowed and events were lost, rescanning".to_string());
+        self.roots
+            .iter()
+            .map(|root| FsEvent::new(FsEventKind::Overflow, root.path.clone(), true))
+            .collect()
+    }
+}
+
+impl AsRawFd for FanotifyBackend {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fanotify.as_raw_fd()
+    }
+}
+
+impl Backend for FanotifyBackend {
+    /// Each root counts as a single watched directory, since nothing is walked.
+    fn watch_root(&mut self, root: &Path) -> Result<WatchStats, String> {
+        let canonical = root.canonicalize()
+            .map_err(|e| format!("Can't watch {}: {}", root.display(), e))?;
+        self.mark(&canonical)?;
+        self.roots.push(Root {
+            path: root.to_path_buf(),
+            canonical,
+        });
+
+        Ok(WatchStats {
+            requested: 1,
+            watched: 1,
+        })
+    }
+
+    fn next_events(&mut self) -> Result<Vec<FsEvent>, String> {
+        let mut buffer = std::mem::take(&mut self.buffer);
+        let len = self.fanotify.read(&mut buffer)
+            .map_err(|e| format!("Error reading fanotify events: {}", e))?;
+
+        let mut events = vec!();
+        let mut offset = 0;
+        while offset + std::mem::size_of::<EventMetadata>() <= len {
+            let meta: EventMetadata = unsafe {
+                std::ptr::read_unaligned(buffer[offset..].as_ptr() as *const EventMetadata)
+            };
+            let end = offset + meta.event_len as usize;
+            if meta.fd >= 0 {
+                unsafe { libc::close(meta.fd) };
+            }
+
+            if meta.mask & FAN_Q_OVERFLOW != 0 {
+                events.extend(self.overflowed());
+            }
+            else {
+                let entries = parse_entries(&buffer[offset + meta.metadata_len as usize..end]);
+                events.extend(self.translate(meta.mask, entries));
+            }
+            offset = end;
+        }
+
+        self.buffer = buffer;
+        Ok(events)
+    }
+
+    fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+}
+
+/// Read the directory handle and name records that follow an event.
+fn parse_entries(mut info: &[u8]) -> Vec<Entry> {
+    let mut entries = vec!();
+    while info.len() >= std::mem::size_of::<InfoHeader>() {
+        let header: InfoHeader = unsafe {
+            std::ptr::read_unaligned(info.as_ptr() as *const InfoHeader)
+        };
+        let len = header.len as usize;
+        if len == 0 || len > info.len() {
+            break;
+        }
+        let record = &info[std::mem::size_of::<InfoHeader>()..len];
+        info = &info[len..];
+
+        match header.info_type {
+            FAN_EVENT_INFO_TYPE_DFID_NAME | FAN_EVENT_INFO_TYPE_OLD_DFID_NAME | FAN_EVENT_INFO_TYPE_NEW_DFID_NAME => (),
+            _ => continue,
+        }
+
+        // fsid, then `struct file_handle` and its bytes, then the name.
+        let word = |at: usize| i32::from_ne_bytes([record[at], record[at + 1], record[at + 2], record[at + 3]]);
+        let fsid = [word(0), word(4)];
+        let handle_len = 8 + word(8) as usize;
+        let handle_bytes = &record[8..8 + handle_len];
+        let mut handle = vec!(0u32; handle_len.div_ceil(4));
+        unsafe {
+            std::ptr::copy_nonoverlapping(handle_bytes.as_ptr(), handle.as_mut_ptr() as *mut u8, handle_len);
+        }
+        let name = unsafe { CStr::from_ptr(record[8 + handle_len..].as_ptr() as *const c_char) };
+        // "." names the directory itself.
+        let name = match name.to_bytes() {
+            b"." => PathBuf::new(),
+            name => PathBuf::from(OsStr::from_bytes(name)),
+        };
+
+        entries.push(Entry {
+            info_type: header.info_type,
+            fsid,
+            handle,
+            name,
+        });
+    }
+    entries
+}
+
+fn fsid_of(file: &File) -> Result<FsId, Error> {
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::fstatfs(file.as_raw_fd(), &mut stat) } < 0 {
+        return Err(Error::last_os_error());
+    }
+    Ok(unsafe { *(&stat.f_fsid as *const libc::fsid_t as *const FsId) })
+}
+
+#[cfg(test)]
+mod test {
+    use std::error::Error;
+    use tempfile::tempdir;
+    use super::FanotifyBackend;
+    use super::super::{ Backend, FsEvent, FsEventKind, };
+    use super::super::test::events_until;
+
+    #[test]
+    #[ignore = "needs CAP_SYS_ADMIN, run with --ignored as root"]
+    fn file_events() -> Result<(), Box<dyn Error>> {
+        let mut backend = FanotifyBackend::new()?;
+        let dir = tempdir()?;
+        let outside = tempdir()?;
+        backend.watch_root(dir.path())?;
+        let tmp_path = dir.path().join("Fake.txt");
+
+        // Same filesystem, but not under a root.
+        std::fs::write(outside.path().join("elsewhere.txt"), "hi")?;
+        std::fs::write(&tmp_path, "hi")?;
+        let events = events_until(&mut backend, |e| e.kind == FsEventKind::Modified);
+        assert_eq!(vec!(
+            FsEvent::new(FsEventKind::Created, tmp_path.clone(), false),
+            FsEvent::new(FsEventKind::Modified, tmp_path.clone(), false),
+        ), events);
+
+        let renamed = dir.path().join("Renamed.txt");
+        std::fs::rename(&tmp_path, &renamed)?;
+        std::fs::create_dir(dir.path().join("sub"))?;
+        std::fs::remove_file(&renamed)?;
+        let events = events_until(&mut backend, |e| e.kind == FsEventKind::Removed);
+        assert_eq!(vec!(
+            FsEvent::new(FsEventKind::MovedFrom(1), tmp_path, false),
+            FsEvent::new(FsEventKind::MovedTo(1), renamed.clone(), false),
+            FsEvent::new(FsEventKind::Created, dir.path().join("sub"), true),
+            FsEvent::new(FsEventKind::Removed, renamed, false),
+        ), events);
+        Ok(())
+    }
+}
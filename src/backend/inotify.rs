@@ -0,0 +1,435 @@
+use ::inotify::{ Event, EventMask, Inotify, WatchDescriptor, WatchMask, };
+use std::ffi::OsStr;
+use std::os::unix::io::{ AsRawFd, RawFd, };
+use std::path::{ Path, PathBuf, };
+use super::{ walk, Backend, FsEvent, FsEventKind, };
+use super::super::ignores::IgnoreFilter;
+use super::super::watches::{ describe_watch_error, FileId, WatchCount, WatchRegistry, WatchStats, };
+
+// TODO: http://man7.org/linux/man-pages/man7/inotify.7.html
+// sizeof(struct inotify_event) + NAME_MAX + 1
+const BUFFER_SIZE: usize = 4096;
+
+/// Watches every directory in the tree with its own inotify watch.
+pub struct InotifyBackend {
+    inotify: Inotify,
+    ignores: Option<IgnoreFilter>,
+    watches: WatchRegistry,
+    /// Whether we already complained about running out of inotify watches.
+    hit_watch_limit: bool,
+    /// The last directory `MOVED_FROM` seen, waiting for its `MOVED_TO` by cookie.
+    pending_move: Option<(u32, PathBuf)>,
+    warnings: Vec<String>,
+    buffer: Vec<u8>,
+}
+
+impl InotifyBackend {
+    pub fn new(respect_ignores: bool) -> Result<InotifyBackend, String> {
+        Ok(InotifyBackend {
+            inotify: Inotify::init()
+                .map_err(|e| format!("Error starting up inotify: {}", e))?,
+            ignores: if respect_ignores { Some(IgnoreFilter::new()) } else { None },
+            watches: WatchRegistry::new(),
+            hit_watch_limit: false,
+            pending_move: None,
+            warnings: vec!(),
+            buffer: vec!(0u8; BUFFER_SIZE),
+        })
+    }
+
+    /// Find the path for a `WatchDescriptor`.
+    fn get_path(&self, wd: &WatchDescriptor, n: &OsStr) -> Option<PathBuf> {
+        self.watches.path(wd)
+            .map(|p| p.join(n))
+    }
+
+    /// Get the path for an event if one exists.
+    fn get_event_path(&self, event: &Event<&OsStr>) -> Option<PathBuf> {
+        match event.name {
+            Some(p) => self.get_path(&event.wd, p),
+            _ => None,
+        }
+    }
+
+    fn is_ignored(&self, path: &Path) -> bool {
+        self.ignores
+            .as_ref()
+            .map(|ignores| ignores.is_ignored(path, true))
+            .unwrap_or(false)
+    }
+
+    /// Keep the watches in sync with the tree and describe what happened.
+    fn translate(&mut self, event: &Event<&OsStr>) -> Vec<FsEvent> {
+        if event.mask.contains(EventMask::Q_OVERFLOW) {
+            self.warnings.push("inotify queue overflowed and events were lost, rescanning".to_string());
+            return self.rescan();
+        }
+        if event.mask.contains(EventMask::IGNORED) {
+            // The kernel already dropped the watch, usually because the directory is gone.
+            self.watches.remove(&event.wd);
+            return vec!();
+        }
+        if event.mask.contains(EventMask::MOVE_SELF) {
+            self.moved_self(&event.wd);
+            return vec!();
+        }
+
+        let path = match self.get_event_path(event) {
+            Some(path) => path,
+            None       => return vec!(),
+        };
+        let is_dir = event.mask.contains(EventMask::ISDIR);
+
+        if let Some(ignores) = &self.ignores {
+            ignores.notice_change(&path);
+        }
+        if is_dir {
+            self.track_directory(event, &path);
+        }
+
+        let kind = if event.mask.contains(EventMask::CREATE) {
+            FsEventKind::Created
+        }
+        else if event.mask.contains(EventMask::CLOSE_WRITE) {
+            FsEventKind::Modified
+        }
+        else if event.mask.contains(EventMask::DELETE) {
+            FsEventKind::Removed
+        }
+        else if event.mask.contains(EventMask::MOVED_FROM) {
+            FsEventKind::MovedFrom(event.cookie)
+        }
+        else if event.mask.contains(EventMask::MOVED_TO) {
+            FsEventKind::MovedTo(event.cookie)
+        }
+        else if event.mask.contains(EventMask::ATTRIB) {
+            FsEventKind::Attrib
+        }
+        else {
+            return vec!();
+        };
+
+        vec!(FsEvent::new(kind, path, is_dir))
+    }
+
+    /// Watch directories as they appear. A `MOVED_TO` is matched with the preceding
+    /// `MOVED_FROM` by cookie, so a directory renamed within the tree keeps its
+    /// watches under the new name.
+    fn track_directory(&mut self, event: &Event<&OsStr>, path: &Path) {
+        if event.mask.contains(EventMask::MOVED_FROM) {
+            self.pending_move = Some((event.cookie, path.to_path_buf()));
+            return;
+        }
+
+        if event.mask.contains(EventMask::MOVED_TO) {
+            if let Some((cookie, from)) = self.pending_move.take() {
+                if cookie == event.cookie {
+                    self.watches.repath(&from, path);
+                    return;
+                }
+            }
+        }
+
+        if (event.mask.contains(EventMask::CREATE) || event.mask.contains(EventMask::MOVED_TO))
+            && !self.is_ignored(path) {
+            if let Err(e) = self.watch_directories(path) {
+                self.warnings.push(format!("could not watch {}: {}", path.display(), e));
+            }
+        }
+    }
+
+    /// A watched directory moved. Moves within the tree were already handled by
+    /// `track_directory`, so if its source is still pending it left the tree.
+    fn moved_self(&mut self, wd: &WatchDescriptor) {
+        let path = match self.watches.path(wd) {
+            Some(path) => path.to_path_buf(),
+            None       => return,
+        };
+
+        let left_tree = match &self.pending_move {
+            Some((_, from)) => *from == path,
+            None            => false,
+        };
+
+        if self.watches.is_root(&path) {
+            self.warnings.push(format!("watched directory {} was moved away", path.display()));
+            self.unwatch_tree(&path);
+        }
+        else if left_tree {
+            self.pending_move = None;
+            self.unwatch_tree(&path);
+        }
+    }
+
+    /// Catch up after losing events: forget directories that moved or vanished
+    /// and watch any that appeared.
+    fn rescan(&mut self) -> Vec<FsEvent> {
+        self.pending_move = None;
+
+        for wd in self.watches.missing() {
+            self.watches.remove(&wd);
+            let _ = self.inotify.rm_watch(wd);
+        }
+
+        let mut events = vec!();
+        for root in self.watches.roots() {
+            if let Err(e) = self.watch_directories(&root) {
+                self.warnings.push(format!("could not rescan {}: {}", root.display(), e));
+            }
+            events.push(FsEvent::new(FsEventKind::Overflow, root, true));
+        }
+        events
+    }
+
+    /// Stop watching a directory and everything below it.
+    fn unwatch_tree(&mut self, root: &Path) {
+        for wd in self.watches.under(root) {
+            self.watches.remove(&wd);
+            // Failure means the kernel already dropped it, which is what we want anyway.
+            let _ = self.inotify.rm_watch(wd);
+        }
+    }
+
+    /// Watch a directory and everything below it.
+    ///
+    /// Directories the kernel won't watch, most likely because of the
+    /// `fs.inotify.max_user_watches` limit, are reported and skipped so that
+    /// everything else keeps working.
+    fn watch_directories(&mut self, path: &Path) -> Result<WatchStats, String> {
+        let mask = WatchMask::CLOSE_WRITE | WatchMask::MOVE | WatchMask::CREATE
+            | WatchMask::DELETE | WatchMask::ATTRIB
+            | WatchMask::DELETE_SELF | WatchMask::MOVE_SELF;
+        let mut stats = WatchStats::default();
+
+        // The registry makes sure a directory reachable through several roots is
+        // only watched once. Linked directories below a root aren't walked, so
+        // they aren't watched either.
+        for entry in walk(path, self.ignores.is_some())? {
+            match entry {
+                Ok(entry) => {
+                    let path = entry.path();
+                    if !path.is_dir() || (entry.depth() > 0 && entry.path_is_symlink()) {
+                        continue;
+                    }
+                    let id = match FileId::of(path) {
+                        Ok(id) => id,
+                        Err(e) => {
+                            self.warnings.push(format!("couldn't stat {}: {}", path.display(), e));
+                            continue;
+                        },
+                    };
+                    if self.watches.is_watched(id) {
+                        continue;
+                    }
+
+                    stats.requested += 1;
+                    match self.inotify.add_watch(path, mask) {
+                        Ok(wd) => {
+                            stats.watched += 1;
+                            self.watches.insert(wd, path.to_owned(), id);
+                        },
+                        Err(ref e) if e.raw_os_error() == Some(libc::ENOSPC) => {
+                            if !self.hit_watch_limit {
+                                self.hit_watch_limit = true;
+                                self.warnings.push(describe_watch_error(path, e));
+                            }
+                        },
+                        Err(e) => self.warnings.push(describe_watch_error(path, &e)),
+                    }
+                }
+                Err(err) => {
+                    self.warnings.push(format!("couldn't walk directory: {}", err));
+                }
+            }
+        }
+
+        Ok(stats)
+    }
+}
+
+impl AsRawFd for InotifyBackend {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inotify.as_raw_fd()
+    }
+}
+
+impl Backend for InotifyBackend {
+    /// Roots inside an already watched root aren't walked again.
+    fn watch_root(&mut self, root: &Path) -> Result<WatchStats, String> {
+        if self.watches.add_root(root)? {
+            self.watch_directories(root)
+        }
+        else {
+            Ok(WatchStats::default())
+        }
+    }
+
+    fn next_events(&mut self) -> Result<Vec<FsEvent>, String> {
+        let mut buffer = std::mem::take(&mut self.buffer);
+        let events = self.inotify.read_events_blocking(&mut buffer)
+            .map_err(|e| format!("Error reading inotify events: {}", e))?
+            .flat_map(|event| self.translate(&event))
+            .collect();
+        self.buffer = buffer;
+        Ok(events)
+    }
+
+    fn watch_count(&self) -> Option<WatchCount> {
+        Some(self.watches.count())
+    }
+
+    fn take_warnings(&mut self) -> Vec<String> {
+        if let Some(ignores) = &self.ignores {
+            self.warnings.extend(ignores.take_warnings());
+        }
+        std::mem::take(&mut self.warnings)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::error::Error;
+    use std::path::{ Path, PathBuf, };
+    use tempfile::{ tempdir, TempDir, };
+    use super::InotifyBackend;
+    use super::super::{ Backend, FsEvent, FsEventKind, };
+    use std::time::Instant;
+    use super::super::test::{ events_until, read_events, TIMEOUT, };
+
+    fn wait_for(backend: &mut InotifyBackend, found: impl Fn(&FsEvent) -> bool) -> FsEvent {
+        events_until(backend, found).pop().unwrap()
+    }
+
+    /// Read events until the backend holds `count` watches.
+    fn wait_for_watches(backend: &mut InotifyBackend, count: usize) {
+        let deadline = Instant::now() + TIMEOUT;
+        while backend.watches.len() != count {
+            read_events(backend, deadline);
+        }
+    }
+
+    fn watched_paths(backend: &InotifyBackend, root: &Path) -> Vec<PathBuf> {
+        let mut paths = backend.watches.under(root)
+            .iter()
+            .filter_map(|wd| backend.watches.path(wd))
+            .map(Path::to_path_buf)
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn file_events() -> Result<(), Box<dyn Error>> {
+        let dir: TempDir = tempdir()?;
+        let mut backend = InotifyBackend::new(true)?;
+        backend.watch_root(dir.path())?;
+        let tmp_path = dir.path().join("Fake.txt");
+
+        std::fs::write(&tmp_path, "hi")?;
+        let events = events_until(&mut backend, |e| e.kind == FsEventKind::Modified);
+        assert_eq!(vec!(
+            FsEvent::new(FsEventKind::Created, tmp_path.clone(), false),
+            FsEvent::new(FsEventKind::Modified, tmp_path.clone(), false),
+        ), events);
+
+        let renamed = dir.path().join("Renamed.txt");
+        std::fs::rename(&tmp_path, &renamed)?;
+        let events = events_until(&mut backend, |e| matches!(e.kind, FsEventKind::MovedTo(_)));
+        match (events[0].kind, events[1].kind) {
+            (FsEventKind::MovedFrom(a), FsEventKind::MovedTo(b)) => assert_eq!(a, b),
+            other => panic!("Expected a move, got {:?}", other),
+        }
+        assert_eq!((&tmp_path, &renamed), (&events[0].path, &events[1].path));
+        Ok(())
+    }
+
+    #[test]
+    fn new_directories() -> Result<(), Box<dyn Error>> {
+        let dir: TempDir = tempdir()?;
+        std::fs::write(dir.path().join(".ignore"), "skipped/\n")?;
+        let mut backend = InotifyBackend::new(true)?;
+        backend.watch_root(dir.path())?;
+
+        std::fs::create_dir(dir.path().join("skipped"))?;
+        std::fs::create_dir(dir.path().join("added"))?;
+        wait_for(&mut backend, |e| e.path.ends_with("added"));
+        assert_eq!(vec!(dir.path().to_path_buf(), dir.path().join("added")),
+                   watched_paths(&backend, dir.path()));
+
+        std::fs::write(dir.path().join("added/file.txt"), "hi")?;
+        wait_for(&mut backend, |e| e.path == dir.path().join("added/file.txt"));
+        Ok(())
+    }
+
+    #[test]
+    fn directory_moves() -> Result<(), Box<dyn Error>> {
+        let dir: TempDir = tempdir()?;
+        let outside: TempDir = tempdir()?;
+        std::fs::create_dir_all(dir.path().join("old/nested"))?;
+        std::fs::create_dir_all(dir.path().join("gone"))?;
+        let mut backend = InotifyBackend::new(true)?;
+        backend.watch_root(dir.path())?;
+        assert_eq!(4, backend.watches.len());
+
+        // Renamed within the tree, so the watches follow it.
+        std::fs::rename(dir.path().join("old"), dir.path().join("new"))?;
+        std::fs::write(dir.path().join("new/nested/file.txt"), "hi")?;
+        let event = wait_for(&mut backend, |e| e.kind == FsEventKind::Modified);
+        assert_eq!(dir.path().join("new/nested/file.txt"), event.path);
+        assert_eq!(4, backend.watches.len());
+
+        // Moved out of the tree, so it shouldn't be watched anymore.
+        std::fs::rename(dir.path().join("gone"), outside.path().join("gone"))?;
+        wait_for_watches(&mut backend, 3);
+
+        // Deleted, which the kernel reports by dropping the watches.
+        std::fs::remove_dir_all(dir.path().join("new"))?;
+        wait_for_watches(&mut backend, 1);
+        assert_eq!(vec!(dir.path().to_path_buf()), watched_paths(&backend, dir.path()));
+        Ok(())
+    }
+
+    #[test]
+    fn deduplicated_watches() -> Result<(), Box<dyn Error>> {
+        let dir: TempDir = tempdir()?;
+        std::fs::create_dir_all(dir.path().join("src/nested"))?;
+        std::os::unix::fs::symlink(dir.path(), dir.path().join("src/loop"))?;
+        std::os::unix::fs::symlink(dir.path().join("src/nested"), dir.path().join("alias"))?;
+        let mut backend = InotifyBackend::new(true)?;
+        assert_eq!(3, backend.watch_root(dir.path())?.watched);
+        assert_eq!(0, backend.watch_root(&dir.path().join("src"))?.requested);
+        assert_eq!(0, backend.watch_root(&dir.path().join("src/nested"))?.requested);
+        assert_eq!(3, backend.watches.len());
+
+        // Links leading out of the root aren't followed.
+        let outside: TempDir = tempdir()?;
+        std::fs::create_dir_all(outside.path().join("a/b"))?;
+        let linked: TempDir = tempdir()?;
+        std::os::unix::fs::symlink(outside.path(), linked.path().join("out"))?;
+        assert_eq!(1, backend.watch_root(linked.path())?.watched);
+        Ok(())
+    }
+
+    #[test]
+    fn rescan() -> Result<(), Box<dyn Error>> {
+        let dir: TempDir = tempdir()?;
+        std::fs::create_dir_all(dir.path().join("old"))?;
+        let mut backend = InotifyBackend::new(true)?;
+        backend.watch_root(dir.path())?;
+        assert_eq!(2, backend.watches.len());
+
+        // Changes nobody heard about.
+        std::fs::rename(dir.path().join("old"), dir.path().join("new"))?;
+        std::fs::create_dir_all(dir.path().join("added/nested"))?;
+
+        assert_eq!(vec!(FsEvent::new(FsEventKind::Overflow, dir.path().to_path_buf(), true)),
+                   backend.rescan());
+        assert_eq!(vec!(
+            dir.path().to_path_buf(),
+            dir.path().join("added"),
+            dir.path().join("added/nested"),
+            dir.path().join("new"),
+        ), watched_paths(&backend, dir.path()));
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+pub mod fanotify;
+pub mod inotify;
+pub mod poll;
+
+use ignore::{ Walk, WalkBuilder, };
+use ignore::overrides::OverrideBuilder;
+use std::path::{ Path, PathBuf, };
+use super::watches::{ WatchCount, WatchStats, };
+
+/// What happened to a path, as reported by a backend.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FsEventKind {
+    /// The path appeared, by being created rather than moved.
+    Created,
+    /// Written and closed.
+    Modified,
+    /// The path was deleted.
+    Removed,
+    /// First half of a rename, matched to a `MovedTo` with the same cookie.
+    MovedFrom(u32),
+    /// Second half of a rename, or something moved in from elsewhere.
+    MovedTo(u32),
+    /// Permissions, ownership or timestamps changed.
+    Attrib,
+    /// Events were lost and the backend rescanned this root.
+    Overflow,
+}
+
+/// A change to a single path, the same for every backend.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FsEvent {
+    pub kind: FsEventKind,
+    pub path: PathBuf,
+    pub is_dir: bool,
+}
+
+impl FsEvent {
+    pub fn new(kind: FsEventKind, path: PathBuf, is_dir: bool) -> FsEvent {
+        FsEvent { kind, path, is_dir }
+    }
+}
+
+/// A source of filesystem events.
+///
+/// Roots are added up front, then the backend is moved to its own thread where
+/// `next_events` is called in a loop. Keeping up with directories created or
+/// removed after startup is the backend's job.
+pub trait Backend: Send {
+    /// Start watching a directory tree.
+    fn watch_root(&mut self, root: &Path) -> Result<WatchStats, String>;
+
+    /// Block until at least one event is available.
+    fn next_events(&mut self) -> Result<Vec<FsEvent>, String>;
+
+    /// Problems the backend carried on past since it was last asked, like
+    /// directories it couldn't watch.
+    fn take_warnings(&mut self) -> Vec<String> {
+        vec!()
+    }
+
+    /// Keeps count of the watches in use, for backends with one per directory.
+    fn watch_count(&self) -> Option<WatchCount> {
+        None
+    }
+}
+
+/// Walk a tree the same way for every backend: hidden files included, `.git`
+/// skipped, and ignore files respected unless asked not to.
+///
+/// Links aren't followed, so nothing outside the root gets walked. Directories
+/// linked from inside the root are reached under their real path anyway.
+pub fn walk(root: &Path, respect_ignores: bool) -> Result<Walk, String> {
+    let overrides = OverrideBuilder::new(root)
+        .add("!.git")
+        .map_err(|e| format!("Error building overrides: {}", e))?
+        .build()
+        .map_err(|e| format!("Error building overrides: {}", e))?;
+
+    Ok(WalkBuilder::new(root)
+        .standard_filters(respect_ignores)
+        .hidden(false)
+        .overrides(overrides)
+        .build())
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::os::unix::io::AsRawFd;
+    use std::time::{ Duration, Instant, };
+    use super::{ Backend, FsEvent, };
+
+    /// How long to wait for the events a test expects before failing it.
+    pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Read whatever the backend has next, which may be nothing it reports.
+    /// Fails the test instead of hanging it if nothing arrives by the deadline.
+    pub fn read_events(backend: &mut (impl Backend + AsRawFd), deadline: Instant) -> Vec<FsEvent> {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let mut fd = libc::pollfd { fd: backend.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        if unsafe { libc::poll(&mut fd, 1, left.as_millis() as libc::c_int) } <= 0 {
+            panic!("Timed out waiting for events");
+        }
+        backend.next_events().unwrap()
+    }
+
+    /// Read events until one matches, returning everything seen up to it.
+    pub fn events_until(backend: &mut (impl Backend + AsRawFd), found: impl Fn(&FsEvent) -> bool) -> Vec<FsEvent> {
+        let deadline = Instant::now() + TIMEOUT;
+        let mut seen = vec!();
+        loop {
+            for event in read_events(backend, deadline) {
+                let done = found(&event);
+                seen.push(event);
+                if done {
+                    return seen;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::os::unix::fs::MetadataExt;
+use std::path::{ Path, PathBuf, };
+use std::time::Duration;
+use super::{ walk, Backend, FsEvent, FsEventKind, };
+use super::super::watches::WatchStats;
+
+/// What a file looked like last time around.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Stamp {
+    mtime: i64,
+    mtime_nsec: i64,
+    size: u64,
+    ino: u64,
+    is_dir: bool,
+}
+
+type Snapshot = HashMap<PathBuf, Stamp>;
+
+/// Finds changes by walking the trees on an interval and comparing what `stat`
+/// says about each file.
+///
+/// Slower to notice changes and heavier on large trees than inotify, but works
+/// anywhere `stat` does, including network filesystems, FUSE mounts and bind
+/// mounted container volumes where inotify never hears about remote writes.
+pub struct PollBackend {
+    interval: Duration,
+    respect_ignores: bool,
+    roots: Vec<PathBuf>,
+    snapshot: Snapshot,
+}
+
+impl PollBackend {
+    pub fn new(interval: Duration, respect_ignores: bool) -> PollBackend {
+        PollBackend {
+            interval,
+            respect_ignores,
+            roots: vec!(),
+            snapshot: Snapshot::new(),
+        }
+    }
+
+    fn scan(&self, root: &Path, snapshot: &mut Snapshot) -> Result<(), String> {
+        for entry in walk(root, self.respect_ignores)? {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(_)    => continue,
+            };
+            // Files can vanish between being listed and being looked at.
+            if let Ok(meta) = std::fs::metadata(entry.path()) {
+                snapshot.insert(entry.path().to_path_buf(), Stamp {
+                    mtime: meta.mtime(),
+                    mtime_nsec: meta.mtime_nsec(),
+                    size: meta.size(),
+                    ino: meta.ino(),
+                    is_dir: meta.is_dir(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Take a fresh snapshot and describe how it differs from the last one.
+    fn poll(&mut self) -> Result<Vec<FsEvent>, String> {
+        let mut current = Snapshot::new();
+        for root in &self.roots {
+            self.scan(root, &mut current)?;
+        }
+
+        let mut events = vec!();
+        for (path, stamp) in &current {
+            match self.snapshot.get(path) {
+                None => {
+                    events.push(FsEvent::new(FsEventKind::Created, path.clone(), stamp.is_dir));
+                    if !stamp.is_dir {
+                        events.push(FsEvent::new(FsEventKind::Modified, path.clone(), false));
+                    }
+                },
+                // A directory's own timestamps change whenever its entries do,
+                // which the entries report themselves.
+                Some(old) if old != stamp && !stamp.is_dir => {
+                    events.push(FsEvent::new(FsEventKind::Modified, path.clone(), false));
+                },
+                _ => (),
+            }
+        }
+        for (path, stamp) in &self.snapshot {
+            if !current.contains_key(path) {
+                events.push(FsEvent::new(FsEventKind::Removed, path.clone(), stamp.is_dir));
+            }
+        }
+        events.sort_by(|a, b| a.path.cmp(&b.path));
+
+        self.snapshot = current;
+        Ok(events)
+    }
+}
+
+impl Backend for PollBackend {
+    fn watch_root(&mut self, root: &Path) -> Result<WatchStats, String> {
+        let mut snapshot = std::mem::take(&mut self.snapshot);
+        let before = snapshot.values().filter(|stamp| stamp.is_dir).count();
+        self.scan(root, &mut snapshot)?;
+        let dirs = snapshot.values().filter(|stamp| stamp.is_dir).count() - before;
+        self.snapshot = snapshot;
+        self.roots.push(root.to_path_buf());
+
+        Ok(WatchStats {
+            requested: dirs,
+            watched: dirs,
+        })
+    }
+
+    fn next_events(&mut self) -> Result<Vec<FsEvent>, String> {
+        loop {
+            std::thread::sleep(self.interval);
+            let events = self.poll()?;
+            if !events.is_empty() {
+                return Ok(events);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::error::Error;
+    use std::time::Duration;
+    use tempfile::tempdir;
+    use super::PollBackend;
+    use super::super::{ Backend, FsEvent, FsEventKind, };
+
+    #[test]
+    fn detects_changes() -> Result<(), Box<dyn Error>> {
+        let dir = tempdir()?;
+        std::fs::write(dir.path().join("kept.txt"), "one")?;
+        std::fs::write(dir.path().join("gone.txt"), "bye")?;
+        let mut backend = PollBackend::new(Duration::from_millis(1), true);
+        assert_eq!(1, backend.watch_root(dir.path())?.watched);
+        assert_eq!(Vec::<FsEvent>::new(), backend.poll()?);
+
+        std::fs::write(dir.path().join("kept.txt"), "three")?;
+        std::fs::remove_file(dir.path().join("gone.txt"))?;
+        std::fs::create_dir(dir.path().join("sub"))?;
+        std::fs::write(dir.path().join("sub/new.txt"), "hi")?;
+
+        assert_eq!(vec!(
+            FsEvent::new(FsEventKind::Removed, dir.path().join("gone.txt"), false),
+            FsEvent::new(FsEventKind::Modified, dir.path().join("kept.txt"), false),
+            FsEvent::new(FsEventKind::Created, dir.path().join("sub"), true),
+            FsEvent::new(FsEventKind::Created, dir.path().join("sub/new.txt"), false),
+            FsEvent::new(FsEventKind::Modified, dir.path().join("sub/new.txt"), false),
+        ), backend.next_events()?);
+        Ok(())
+    }
+}
@@ -39,7 +39,7 @@ fn run(pager: Arc<Pager2>) -> (Arc<SharedChild>, JoinHandle<()>) {
 */
 
 use std::error::Error;
-fn main() -> Result<(), Box<Error>> {
+fn main() -> Result<(), Box<dyn Error>> {
 /*
     let p = Arc::new(Pager2::new());
 
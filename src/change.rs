@@ -0,0 +1,193 @@
+use std::fmt;
+use std::path::{ Path, PathBuf, };
+use std::time::SystemTime;
+use super::backend::{ FsEvent, FsEventKind, };
+use super::event::EventKind;
+
+/// What happened, in terms of files and directories rather than backend events.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChangeKind {
+    /// A new file appeared. Its contents show up as a `Modified` once written.
+    Created,
+    /// Written and closed.
+    Modified,
+    /// Deleted, or moved out of the watched directories.
+    Removed,
+    /// Moved into place. `from` is missing when it came from outside the watched
+    /// directories.
+    Renamed { from: Option<PathBuf> },
+    /// Permissions, ownership or timestamps changed.
+    Attrib,
+    /// A new directory appeared, and is watched from now on.
+    DirCreated,
+    /// A directory was deleted, or moved out of the watched directories.
+    DirRemoved,
+    /// A directory was moved into place, like `Renamed`.
+    DirRenamed { from: Option<PathBuf> },
+    /// Events were lost, so this watched root was scanned again.
+    Rescan,
+    /// Not a change at all, the watcher just started on this root.
+    Initial,
+}
+
+impl ChangeKind {
+    /// The kind of trigger this change counts as, if it can trigger a run at all.
+    pub fn trigger(&self) -> Option<EventKind> {
+        match self {
+            ChangeKind::Modified       => Some(EventKind::Modified),
+            ChangeKind::Removed        => Some(EventKind::Removed),
+            ChangeKind::Renamed { .. } => Some(EventKind::Renamed),
+            ChangeKind::Attrib         => Some(EventKind::Attrib),
+            ChangeKind::Rescan         => Some(EventKind::Rescan),
+            _                          => None,
+        }
+    }
+}
+
+impl fmt::Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChangeKind::Created           => write!(f, "created"),
+            ChangeKind::Modified          => write!(f, "modified"),
+            ChangeKind::Removed           => write!(f, "removed"),
+            ChangeKind::Renamed { .. }    => write!(f, "renamed"),
+            ChangeKind::Attrib            => write!(f, "attrib"),
+            ChangeKind::DirCreated        => write!(f, "dir-created"),
+            ChangeKind::DirRemoved        => write!(f, "dir-removed"),
+            ChangeKind::DirRenamed { .. } => write!(f, "dir-renamed"),
+            ChangeKind::Rescan            => write!(f, "rescan"),
+            ChangeKind::Initial           => write!(f, "initial"),
+        }
+    }
+}
+
+/// A single change to a watched path, the same whichever backend saw it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChangeEvent {
+    pub kind: ChangeKind,
+    /// Where the file is now, or was before it went away.
+    pub path: PathBuf,
+    /// The watched root `path` was found under.
+    pub root: Option<PathBuf>,
+    /// When fwatch heard about it.
+    pub timestamp: SystemTime,
+}
+
+impl ChangeEvent {
+    pub fn new(kind: ChangeKind, path: PathBuf, root: Option<PathBuf>) -> ChangeEvent {
+        ChangeEvent {
+            kind,
+            path,
+            root,
+            timestamp: SystemTime::now(),
+        }
+    }
+}
+
+/// Turns backend events into `ChangeEvent`s, pairing up the halves of renames.
+pub struct Translator {
+    roots: Vec<PathBuf>,
+}
+
+impl Translator {
+    pub fn new(roots: Vec<PathBuf>) -> Translator {
+        Translator { roots }
+    }
+
+    /// Find the most specific root a path was found under.
+    pub fn root_of(&self, path: &Path) -> Option<&Path> {
+        self.roots
+            .iter()
+            .filter(|root| path.starts_with(root))
+            .max_by_key(|root| root.components().count())
+            .map(PathBuf::as_path)
+    }
+
+    fn change(&self, kind: ChangeKind, path: &Path) -> ChangeEvent {
+        ChangeEvent::new(kind, path.to_path_buf(), self.root_of(path).map(Path::to_path_buf))
+    }
+
+    /// Translate one read's worth of events.
+    ///
+    /// Backends report the two halves of a rename back to back, so a
+    /// `MovedFrom` not followed by its `MovedTo` was moved out of the tree.
+    pub fn translate(&self, events: &[FsEvent]) -> Vec<ChangeEvent> {
+        let mut changes = vec!();
+        let mut pending: Option<(u32, &FsEvent)> = None;
+
+        for event in events {
+            if let Some((cookie, from)) = pending.take() {
+                if event.kind == FsEventKind::MovedTo(cookie) {
+                    let from = Some(from.path.clone());
+                    let kind = if event.is_dir { ChangeKind::DirRenamed { from } } else { ChangeKind::Renamed { from } };
+                    changes.push(self.change(kind, &event.path));
+                    continue;
+                }
+                changes.push(self.change(removed(from.is_dir), &from.path));
+            }
+
+            let kind = match (event.kind, event.is_dir) {
+                (FsEventKind::MovedFrom(cookie), _) => {
+                    pending = Some((cookie, event));
+                    continue;
+                },
+                (FsEventKind::Overflow, _)       => ChangeKind::Rescan,
+                (FsEventKind::Created, false)    => ChangeKind::Created,
+                (FsEventKind::Created, true)     => ChangeKind::DirCreated,
+                (FsEventKind::Modified, _)       => ChangeKind::Modified,
+                (FsEventKind::Removed, is_dir)   => removed(is_dir),
+                (FsEventKind::MovedTo(_), false) => ChangeKind::Renamed { from: None },
+                (FsEventKind::MovedTo(_), true)  => ChangeKind::DirRenamed { from: None },
+                (FsEventKind::Attrib, _)         => ChangeKind::Attrib,
+            };
+            changes.push(self.change(kind, &event.path));
+        }
+
+        if let Some((_, from)) = pending {
+            changes.push(self.change(removed(from.is_dir), &from.path));
+        }
+        changes
+    }
+}
+
+fn removed(is_dir: bool) -> ChangeKind {
+    if is_dir { ChangeKind::DirRemoved } else { ChangeKind::Removed }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{ Path, PathBuf, };
+    use super::{ ChangeKind, Translator, };
+    use super::super::backend::{ FsEvent, FsEventKind, };
+
+    fn event(kind: FsEventKind, path: &str, is_dir: bool) -> FsEvent {
+        FsEvent::new(kind, PathBuf::from(path), is_dir)
+    }
+
+    #[test]
+    fn translation() {
+        let translator = Translator::new(vec!(PathBuf::from("/repo"), PathBuf::from("/repo/sub")));
+        let changes = translator.translate(&[
+            event(FsEventKind::Created, "/repo/a.tmp", false),
+            event(FsEventKind::Modified, "/repo/a.tmp", false),
+            event(FsEventKind::MovedFrom(1), "/repo/a.tmp", false),
+            event(FsEventKind::MovedTo(1), "/repo/sub/a.txt", false),
+            event(FsEventKind::MovedFrom(2), "/repo/old", true),
+            event(FsEventKind::MovedTo(3), "/repo/new", true),
+            event(FsEventKind::MovedFrom(4), "/repo/gone.txt", false),
+        ]);
+
+        assert_eq!(vec!(
+            (ChangeKind::Created, "/repo/a.tmp"),
+            (ChangeKind::Modified, "/repo/a.tmp"),
+            (ChangeKind::Renamed { from: Some(PathBuf::from("/repo/a.tmp")) }, "/repo/sub/a.txt"),
+            (ChangeKind::DirRemoved, "/repo/old"),
+            (ChangeKind::DirRenamed { from: None }, "/repo/new"),
+            (ChangeKind::Removed, "/repo/gone.txt"),
+        ), changes.iter()
+            .map(|change| (change.kind.clone(), change.path.to_str().unwrap()))
+            .collect::<Vec<_>>());
+        assert_eq!(Some(Path::new("/repo/sub")), changes[2].root.as_deref());
+        assert_eq!(Some(Path::new("/repo")), changes[3].root.as_deref());
+    }
+}
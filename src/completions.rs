@@ -0,0 +1,71 @@
+use clap::{ App, Shell, };
+use std::io::Write;
+
+/// Lists profile names when completing, from whichever config file is nearest.
+const PROFILES: &str = "fwatch profiles --names 2>/dev/null";
+
+/// Write completions for a shell, completing `--profile` with the profiles of
+/// the config file nearest to wherever the user is at the time. That part is
+/// only for bash, zsh and fish, the others complete flag names only.
+pub fn generate(mut app: App, shell: Shell, out: &mut dyn Write) -> Result<(), String> {
+    let mut script = vec!();
+    app.gen_completions_to("fwatch", shell, &mut script);
+    let script = String::from_utf8(script)
+        .map_err(|e| format!("Error generating completions: {}", e))?;
+
+    out.write_all(complete_profiles(script, shell).as_bytes())
+        .map_err(|e| format!("Error writing completions: {}", e))
+}
+
+/// Patch what clap generates for `--profile`, which would complete file names.
+fn complete_profiles(script: String, shell: Shell) -> String {
+    match shell {
+        Shell::Bash => script.replacen(
+            "--profile)\n                    COMPREPLY=($(compgen -f \"${cur}\"))",
+            &format!("--profile)\n                    COMPREPLY=($(compgen -W \"$({})\" -- \"${{cur}}\"))", PROFILES),
+            1),
+        Shell::Zsh  => {
+            let script = script
+                .lines()
+                .map(|line| if line.starts_with("'--profile=[") && line.ends_with("]' \\") {
+                    format!("{}:profile:_fwatch_profiles' \\", &line[..line.len() - 3])
+                }
+                else {
+                    line.to_string()
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+            script.replacen("\n_fwatch \"$@\"", &format!("
+(( $+functions[_fwatch_profiles] )) ||
+_fwatch_profiles() {{
+    local profiles; profiles=(${{(f)\"$({})\"}})
+    _describe -t profiles 'fwatch profiles' profiles \"$@\"
+}}
+
+_fwatch \"$@\"", PROFILES), 1)
+        },
+        Shell::Fish => format!(
+            "{}complete -c fwatch -n \"__fish_seen_subcommand_from run\" -l profile -r -f -a \"({})\"\n",
+            script, PROFILES),
+        _           => script,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::Shell;
+    use super::generate;
+
+    #[test]
+    fn every_shell() {
+        for shell in &Shell::variants() {
+            let mut script = vec!();
+            generate(super::super::build_cli(), shell.parse().unwrap(), &mut script).unwrap();
+            let script = String::from_utf8(script).unwrap();
+            assert!(script.contains("profiles"), "{} completions are missing subcommands", shell);
+            if ["bash", "zsh", "fish"].contains(shell) {
+                assert!(script.contains("fwatch profiles --names"), "{} completions don't complete profiles", shell);
+            }
+        }
+    }
+}
@@ -0,0 +1,577 @@
+use clap::ArgMatches;
+use fwatch::WatcherBuilder;
+use fwatch::backend::fanotify::FanotifyBackend;
+use fwatch::backend::inotify::InotifyBackend;
+use fwatch::backend::poll::PollBackend;
+use fwatch::debounce::parse_duration;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{ Path, PathBuf, };
+use std::time::Duration;
+use super::notify::Notifications;
+use super::policy::OnBusy;
+use super::process::{ parse_signal, Stopper, };
+use super::rule::RuleSpec;
+use super::runtime::Runtime;
+
+/// What the config file is called in a project.
+pub const FILE_NAME: &str = "fwatch.toml";
+
+/// Everything `fwatch run` can be told, from flags or from `fwatch.toml`.
+///
+/// Keys in the file are named after the long flags, plus `roots` for the
+/// directories to watch, `env` for extra environment variables and `[[rule]]`
+/// tables for `--rule`. `[profile.<name>]` tables take the same keys, and are
+/// layered over the rest of the file when picked with `--profile`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Settings {
+    roots: Option<Vec<PathBuf>>,
+    command: Option<Vec<String>>,
+    shell: Option<bool>,
+    pager: Option<bool>,
+    ext: Option<Vec<String>>,
+    regex: Option<String>,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    trigger: Option<Vec<String>>,
+    initial: Option<bool>,
+    no_ignore: Option<bool>,
+    debounce: Option<String>,
+    poll: Option<bool>,
+    fanotify: Option<bool>,
+    poll_interval: Option<String>,
+    on_busy: Option<String>,
+    jobs: Option<usize>,
+    signal: Option<String>,
+    kill_timeout: Option<String>,
+    then: Option<Vec<String>>,
+    on_success: Option<String>,
+    on_failure: Option<String>,
+    notify: Option<String>,
+    notify_success: Option<String>,
+    notify_failure: Option<String>,
+    env: Option<BTreeMap<String, String>>,
+    rule: Option<Vec<RuleSpec>>,
+    /// What a profile is for, shown by `fwatch profiles`.
+    description: Option<String>,
+    profile: Option<BTreeMap<String, Settings>>,
+}
+
+/// Find the config file: the one given, or the nearest `fwatch.toml` in the
+/// current directory or above.
+pub fn locate(given: Option<&Path>) -> Result<Option<PathBuf>, String> {
+    if let Some(path) = given {
+        return Ok(Some(path.to_path_buf()));
+    }
+
+    let cwd = std::env::current_dir()
+        .map_err(|e| format!("Error finding the current directory: {}", e))?;
+    Ok(discover(&cwd))
+}
+
+fn discover(from: &Path) -> Option<PathBuf> {
+    from.ancestors()
+        .map(|dir| dir.join(FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+impl Settings {
+    /// Read a config file. Roots are relative to the directory it is in.
+    pub fn load(path: &Path) -> Result<Settings, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Error reading {}: {}", path.display(), e))?;
+        let mut settings: Settings = toml::from_str(&text)
+            .map_err(|e| format!("Error in {}: {}", path.display(), e))?;
+
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        settings.resolve_roots(dir);
+        for (name, profile) in settings.profile.iter_mut().flatten() {
+            if profile.profile.is_some() {
+                return Err(format!("Error in {}: profile {:?} has profiles of its own", path.display(), name));
+            }
+            profile.resolve_roots(dir);
+        }
+        Ok(settings)
+    }
+
+    fn resolve_roots(&mut self, dir: &Path) {
+        for root in self.roots.iter_mut().flatten() {
+            *root = dir.join(&*root);
+        }
+    }
+
+    /// Layer a profile over the rest of the file.
+    pub fn with_profile(mut self, name: &str) -> Result<Settings, String> {
+        let names = self.profiles()
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .collect::<Vec<_>>();
+        match self.profile.as_mut().and_then(|profiles| profiles.remove(name)) {
+            Some(profile)            => Ok(profile.or(self)),
+            None if names.is_empty() => Err(format!("No profile {:?}, there are no profiles in {}", name, FILE_NAME)),
+            None                     => Err(format!("No profile {:?}, expected one of {}", name, names.join(", "))),
+        }
+    }
+
+    /// Each profile's name and description.
+    pub fn profiles(&self) -> Vec<(&str, Option<&str>)> {
+        self.profile
+            .iter()
+            .flatten()
+            .map(|(name, profile)| (name.as_str(), profile.description.as_deref()))
+            .collect()
+    }
+
+    /// The flags given to `fwatch run`.
+    pub fn from_matches(matches: &ArgMatches) -> Result<Settings, String> {
+        let values = |name| matches.values_of(name).map(|values| values.map(str::to_string).collect());
+        let value = |name| matches.value_of(name).map(str::to_string);
+        let flag = |name| match (matches.is_present(name), matches.is_present(format!("no-{}", name))) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _         => None,
+        };
+
+        Ok(Settings {
+            roots: matches.values_of_os("dirs").map(|dirs| dirs.map(PathBuf::from).collect()),
+            command: values("command"),
+            shell: flag("shell"),
+            pager: flag("pager"),
+            ext: values("ext"),
+            regex: value("regex"),
+            include: values("include"),
+            exclude: values("exclude"),
+            trigger: values("trigger"),
+            initial: flag("initial"),
+            no_ignore: flag("no-ignore"),
+            debounce: value("debounce"),
+            poll: flag("poll"),
+            fanotify: flag("fanotify"),
+            poll_interval: value("poll-interval"),
+            on_busy: value("on-busy"),
+            jobs: matches.value_of("jobs")
+                .map(|jobs| jobs.parse().map_err(|e| format!("Invalid --jobs {:?}: {}", jobs, e)))
+                .transpose()?,
+            signal: value("signal"),
+            kill_timeout: value("kill-timeout"),
+            then: values("then"),
+            on_success: value("on-success"),
+            on_failure: value("on-failure"),
+            notify: value("notify"),
+            notify_success: value("notify-success"),
+            notify_failure: value("notify-failure"),
+            env: None,
+            rule: matches.values_of("rule")
+                .map(|rules| rules.map(str::parse).collect())
+                .transpose()?,
+            description: None,
+            profile: None,
+        })
+    }
+
+    /// Fill in whatever these settings leave out from `file`. `shell` comes with
+    /// the command, so a command given here isn't run the way the file's is.
+    pub fn or(self, file: Settings) -> Settings {
+        let shell = if self.command.is_some() { self.shell } else { self.shell.or(file.shell) };
+        Settings {
+            roots: self.roots.or(file.roots),
+            command: self.command.or(file.command),
+            shell,
+            pager: self.pager.or(file.pager),
+            ext: self.ext.or(file.ext),
+            regex: self.regex.or(file.regex),
+            include: self.include.or(file.include),
+            exclude: self.exclude.or(file.exclude),
+            trigger: self.trigger.or(file.trigger),
+            initial: self.initial.or(file.initial),
+            no_ignore: self.no_ignore.or(file.no_ignore),
+            debounce: self.debounce.or(file.debounce),
+            poll: self.poll.or(file.poll),
+            fanotify: self.fanotify.or(file.fanotify),
+            poll_interval: self.poll_interval.or(file.poll_interval),
+            on_busy: self.on_busy.or(file.on_busy),
+            jobs: self.jobs.or(file.jobs),
+            signal: self.signal.or(file.signal),
+            kill_timeout: self.kill_timeout.or(file.kill_timeout),
+            then: self.then.or(file.then),
+            on_success: self.on_success.or(file.on_success),
+            on_failure: self.on_failure.or(file.on_failure),
+            notify: self.notify.or(file.notify),
+            notify_success: self.notify_success.or(file.notify_success),
+            notify_failure: self.notify_failure.or(file.notify_failure),
+            env: self.env.or(file.env),
+            rule: self.rule.or(file.rule),
+            description: self.description.or(file.description),
+            profile: self.profile.or(file.profile),
+        }
+    }
+
+    pub fn pager(&self) -> bool {
+        self.pager.unwrap_or(false)
+    }
+
+    /// Make sure everything would work, short of watching anything.
+    pub fn check(&self) -> Result<(), String> {
+        self.runtime()?;
+        self.poll_interval.as_deref().map(parse_duration).transpose()?;
+        for root in self.roots()? {
+            if !root.is_dir() {
+                return Err(format!("Root {} is not a directory", root.display()));
+            }
+        }
+        self.filters(&mut WatcherBuilder::new())
+    }
+
+    /// Check a whole file: every profile, and the file on its own unless it
+    /// only sets up profiles.
+    pub fn check_file(&self) -> Result<(), String> {
+        if self.profile.is_none() || self.command.is_some() || self.rule.is_some() {
+            self.check()?;
+        }
+        for (name, _) in self.profiles() {
+            self.clone()
+                .with_profile(name)?
+                .check()
+                .map_err(|e| format!("In profile {}: {}", name, e))?;
+        }
+        Ok(())
+    }
+
+    fn roots(&self) -> Result<&[PathBuf], String> {
+        match &self.roots {
+            Some(roots) if !roots.is_empty() => Ok(roots),
+            _ => Err(format!("No directories to watch, list some on the command line or as roots in {}", FILE_NAME)),
+        }
+    }
+
+    /// The rule standing in for the command when there isn't one, and the
+    /// rules that come after it.
+    fn rules(&self) -> (Option<&RuleSpec>, &[RuleSpec]) {
+        let rules = self.rule.as_deref().unwrap_or(&[]);
+        match (&self.command, rules.split_first()) {
+            (None, Some((first, rest))) => (Some(first), rest),
+            _                           => (None, rules),
+        }
+    }
+
+    fn on_busy(&self) -> Result<OnBusy, String> {
+        Ok(match (self.on_busy.as_deref().map(str::parse).transpose()?, self.jobs) {
+            (Some(OnBusy::Parallel(_)), Some(jobs)) => OnBusy::Parallel(jobs),
+            (Some(on_busy), _)                      => on_busy,
+            (None, _)                               => OnBusy::Restart,
+        })
+    }
+
+    /// Set up what to run, without starting anything.
+    pub fn runtime(&self) -> Result<Runtime, String> {
+        let command_only = [
+            ("shell", self.shell == Some(true)),
+            ("ext", self.ext.is_some()),
+            ("regex", self.regex.is_some()),
+            ("include", self.include.is_some()),
+            ("then", self.then.is_some()),
+            ("on-success", self.on_success.is_some()),
+            ("on-failure", self.on_failure.is_some()),
+        ];
+        if self.command.is_none() {
+            if let Some((name, _)) = command_only.iter().find(|(_, set)| *set) {
+                return Err(format!("'{}' only applies to the command, which isn't set", name));
+            }
+        }
+
+        let (first_rule, extra_rules) = self.rules();
+        let mut runtime = match (&self.command, first_rule) {
+            (Some(template), _) if self.shell == Some(true) => Runtime::new_shell(template.clone())?,
+            (Some(template), _)                             => Runtime::new(template.clone())?,
+            (None, Some(rule))                              => Runtime::new_shell(vec!(rule.script.clone()))?,
+            (None, None)                                    => return Err(format!(
+                "No command to run, give one after -- or set command or a rule in {}", FILE_NAME)),
+        };
+
+        for then in self.then.iter().flatten() {
+            runtime.add_then(then)?;
+        }
+        if let Some(script) = &self.on_success {
+            runtime.set_on_success(script)?;
+        }
+        if let Some(script) = &self.on_failure {
+            runtime.set_on_failure(script)?;
+        }
+        let on_busy = self.on_busy()?;
+        runtime.set_on_busy(first_rule
+            .and_then(|rule| rule.on_busy)
+            .unwrap_or(on_busy));
+
+        for rule in extra_rules {
+            runtime.add_rule(&rule.script)?
+                .set_on_busy(rule.on_busy.unwrap_or(on_busy));
+        }
+
+        let notifier = |specific: &Option<String>| specific.as_ref()
+            .or(self.notify.as_ref())
+            .map(|how| how.parse())
+            .transpose();
+        runtime.set_notifications(Notifications {
+            success: notifier(&self.notify_success)?,
+            failure: notifier(&self.notify_failure)?,
+        });
+
+        let mut stopper = Stopper::default();
+        if let Some(signal) = &self.signal {
+            stopper.signal = parse_signal(signal)?;
+        }
+        if let Some(timeout) = &self.kill_timeout {
+            stopper.timeout = parse_duration(timeout)?;
+        }
+        runtime.set_stopper(stopper);
+
+        if let Some(env) = &self.env {
+            runtime.set_env(env.clone().into_iter().collect());
+        }
+        Ok(runtime)
+    }
+
+    /// Set up what to watch, with rules in the same order as `runtime`.
+    pub fn watcher(&self) -> Result<WatcherBuilder, String> {
+        let mut watcher = WatcherBuilder::new();
+        let respect_ignores = self.no_ignore != Some(true);
+        watcher.respect_ignores(respect_ignores);
+
+        if self.poll == Some(true) {
+            let interval = match &self.poll_interval {
+                Some(interval) => parse_duration(interval)?,
+                None           => Duration::from_secs(1),
+            };
+            watcher.backend(Box::new(PollBackend::new(interval, respect_ignores)));
+        }
+        else if self.fanotify == Some(true) {
+            // Marking is what needs the privileges, so try it on every root now.
+            let roots = self.roots()?;
+            let fanotify = FanotifyBackend::new()
+                .and_then(|mut backend| {
+                    for root in roots {
+                        backend.mark(root)?;
+                    }
+                    Ok(backend)
+                });
+            match fanotify {
+                Ok(backend) => watcher.backend(Box::new(backend)),
+                Err(e)      => {
+                    println!("Warning, {}, falling back to inotify", e);
+                    watcher.backend(Box::new(InotifyBackend::new(respect_ignores)?))
+                },
+            };
+        }
+
+        self.filters(&mut watcher)?;
+        watcher.initial(self.initial == Some(true));
+        for root in self.roots()? {
+            watcher.root(root);
+        }
+        Ok(watcher)
+    }
+
+    /// Set up each rule's filter, triggers and debounce window.
+    fn filters(&self, watcher: &mut WatcherBuilder) -> Result<(), String> {
+        let triggers = self.trigger
+            .as_ref()
+            .map(|triggers| triggers
+                .iter()
+                .map(|trigger| trigger.parse())
+                .collect::<Result<Vec<_>, _>>())
+            .transpose()?;
+        let debounce = self.debounce
+            .as_deref()
+            .map(parse_duration)
+            .transpose()?;
+
+        // Settings every rule starts from.
+        let defaults = |watcher: &mut WatcherBuilder| -> Result<(), String> {
+            if let Some(triggers) = &triggers {
+                watcher.triggers(triggers.clone());
+            }
+            if let Some(window) = debounce {
+                watcher.debounce(window);
+            }
+            for glob in self.exclude.iter().flatten() {
+                watcher.filter().add_exclude(glob)?;
+            }
+            Ok(())
+        };
+        let configure = |watcher: &mut WatcherBuilder, rule: &RuleSpec| -> Result<(), String> {
+            defaults(watcher)?;
+            for glob in &rule.globs {
+                watcher.filter().add_include(glob)?;
+            }
+            if let Some(window) = rule.debounce {
+                watcher.debounce(window);
+            }
+            Ok(())
+        };
+
+        let (first_rule, extra_rules) = self.rules();
+        if let Some(rule) = first_rule {
+            configure(watcher, rule)?;
+        }
+        else {
+            defaults(watcher)?;
+            let filter = watcher.filter();
+            for ext in self.ext.iter().flatten() {
+                filter.add_extension(ext);
+            }
+
+            if let Some(re) = &self.regex {
+                filter.set_regex(Regex::new(re).map_err(|e| format!("Invalid regex: {}", e))?);
+            }
+
+            for glob in self.include.iter().flatten() {
+                filter.add_include(glob)?;
+            }
+        }
+
+        for rule in extra_rules {
+            configure(watcher.add_rule(), rule)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::error::Error;
+    use tempfile::tempdir;
+    use super::{ discover, Settings, };
+
+    const EXAMPLE: &str = r#"
+roots = ["src"]
+command = ["cargo", "check"]
+ext = ["rs"]
+debounce = "200ms"
+on-busy = "queue"
+
+[env]
+RUST_BACKTRACE = "1"
+
+[[rule]]
+globs = ["*.scss"]
+script = "sass build"
+debounce = "1s"
+"#;
+
+    #[test]
+    fn loading() -> Result<(), Box<dyn Error>> {
+        let dir = tempdir()?;
+        let path = dir.path().join("fwatch.toml");
+        std::fs::write(&path, EXAMPLE)?;
+        let settings = Settings::load(&path)?;
+        assert_eq!(Some(vec!(dir.path().join("src"))), settings.roots);
+        assert_eq!(1, settings.rule.as_ref().unwrap().len());
+        assert!(settings.runtime().is_ok());
+
+        std::fs::write(&path, "comand = [\"make\"]")?;
+        assert!(Settings::load(&path).unwrap_err().contains("unknown field `comand`"));
+        Ok(())
+    }
+
+    #[test]
+    fn flags_win() {
+        let file = Settings {
+            command: Some(vec!("make".to_string())),
+            debounce: Some("1s".to_string()),
+            pager: Some(true),
+            ..Settings::default()
+        };
+        let flags = Settings {
+            debounce: Some("50ms".to_string()),
+            ..Settings::default()
+        };
+        let merged = flags.or(file.clone());
+        assert_eq!(Some("50ms".to_string()), merged.debounce);
+        assert_eq!(Some(vec!("make".to_string())), merged.command);
+        assert!(merged.pager());
+
+        let matches = super::super::build_cli().get_matches_from(vec!("fwatch", "run", "--no-pager"));
+        let flags = Settings::from_matches(matches.subcommand_matches("run").unwrap()).unwrap();
+        assert!(!flags.or(file).pager());
+
+        let file = Settings {
+            command: Some(vec!("make && make test".to_string())),
+            shell: Some(true),
+            ..Settings::default()
+        };
+        let flags = Settings {
+            command: Some(vec!("cargo".to_string(), "test".to_string())),
+            ..Settings::default()
+        };
+        assert_eq!(None, flags.or(file).shell);
+    }
+
+    #[test]
+    fn checking() {
+        let settings = |text: &str| -> Settings { toml::from_str(text).unwrap() };
+        assert!(settings("roots = [\".\"]\ncommand = [\"make\"]").check().is_ok());
+        assert!(settings("roots = [\".\"]").check().is_err());
+        assert!(settings("command = [\"make\"]").check().is_err());
+        assert!(settings("roots = [\"/nowhere\"]\ncommand = [\"make\"]").check().is_err());
+        assert!(settings("roots = [\".\"]\ncommand = [\"make\"]\ndebounce = \"soon\"").check().is_err());
+        assert!(settings("roots = [\".\"]\next = [\"rs\"]\n[[rule]]\nglobs = [\"*\"]\nscript = \"make\"").check().is_err());
+    }
+
+    #[test]
+    fn fanotify_fallback() -> Result<(), Box<dyn Error>> {
+        // procfs can't be marked even with privileges, inotify watches it fine.
+        let settings: Settings = toml::from_str("roots = [\"/proc/sys/fs\"]\nfanotify = true")?;
+        let watcher = settings.watcher()?.build()?;
+        // fanotify would count the root as one watch, inotify walks the tree.
+        assert!(watcher.watch_stats().watched > 1);
+        Ok(())
+    }
+
+    #[test]
+    fn profiles() -> Result<(), Box<dyn Error>> {
+        let dir = tempdir()?;
+        let path = dir.path().join("fwatch.toml");
+        std::fs::write(&path, r#"
+roots = ["."]
+debounce = "200ms"
+
+[profile.test]
+description = "Run the tests"
+command = ["cargo", "test"]
+
+[profile.docs]
+roots = ["docs"]
+command = ["mdbook", "build"]
+debounce = "1s"
+"#)?;
+        let settings = Settings::load(&path)?;
+        assert_eq!(vec!(("docs", None), ("test", Some("Run the tests"))), settings.profiles());
+
+        let docs = settings.clone().with_profile("docs")?;
+        assert_eq!(Some(vec!(dir.path().join("docs"))), docs.roots);
+        assert_eq!(Some("1s".to_string()), docs.debounce);
+        let test = settings.clone().with_profile("test")?;
+        assert_eq!(Some(vec!(dir.path().join("."))), test.roots);
+        assert_eq!(Some("200ms".to_string()), test.debounce);
+        assert!(settings.clone().with_profile("bench").unwrap_err().contains("docs, test"));
+
+        // docs/ doesn't exist.
+        assert!(settings.check_file().unwrap_err().contains("In profile docs"));
+        std::fs::create_dir(dir.path().join("docs"))?;
+        assert!(settings.check_file().is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn discovery() -> Result<(), Box<dyn Error>> {
+        let dir = tempdir()?;
+        let nested = dir.path().join("a/b");
+        std::fs::create_dir_all(&nested)?;
+        assert_eq!(None, discover(&nested));
+        std::fs::write(dir.path().join("fwatch.toml"), "")?;
+        assert_eq!(Some(dir.path().join("fwatch.toml")), discover(&nested));
+        Ok(())
+    }
+}
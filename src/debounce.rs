@@ -0,0 +1,164 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::time::{ Duration, Instant, };
+use super::change::ChangeEvent;
+
+/// Collects changed paths until the filesystem has been quiet for `window`.
+pub struct Debouncer {
+    window: Duration,
+    deadline: Option<Instant>,
+    /// Changes in the order they arrived.
+    changes: Vec<ChangeEvent>,
+}
+
+/// The set of paths that changed during one quiet-period window.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Batch {
+    /// The most recent change.
+    pub latest: ChangeEvent,
+    /// Every path that changed, deduplicated and sorted.
+    pub changed: Vec<PathBuf>,
+    /// Which of the watcher's rules the changes matched, in the order the rules
+    /// were added.
+    pub rule: usize,
+}
+
+impl Batch {
+    /// Fold a later batch into this one.
+    pub fn merge(&mut self, later: Batch) {
+        let changed = self.changed
+            .drain(..)
+            .chain(later.changed)
+            .collect::<BTreeSet<_>>();
+        self.changed = changed.into_iter().collect();
+        self.latest = later.latest;
+    }
+}
+
+impl Debouncer {
+    pub fn new(window: Duration) -> Debouncer {
+        Debouncer {
+            window,
+            deadline: None,
+            changes: vec!(),
+        }
+    }
+
+    /// Record a change, pushing the deadline back by another window.
+    pub fn add(&mut self, change: ChangeEvent) {
+        self.changes.push(change);
+        self.deadline = Some(Instant::now() + self.window);
+    }
+
+    /// How long to wait for more events before the batch is ready, if one is pending.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Take the pending batch once its quiet period has elapsed.
+    pub fn take_ready(&mut self) -> Option<Batch> {
+        match self.deadline {
+            Some(deadline) if deadline <= Instant::now() => {
+                self.deadline = None;
+                let changes = std::mem::take(&mut self.changes);
+                let changed = changes.iter()
+                    .map(|change| change.path.clone())
+                    .collect::<BTreeSet<_>>();
+                Some(Batch {
+                    latest: changes.into_iter().last()?,
+                    changed: changed.into_iter().collect(),
+                    rule: 0,
+                })
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Longer than anyone would wait, short enough to add to an `Instant` safely.
+const MAX_DURATION: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Parse a human duration such as `200ms`, `2s` or a bare number of milliseconds,
+/// up to a year.
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    let (number, unit) = match input.find(|c: char| !c.is_ascii_digit()) {
+        Some(idx) => input.split_at(idx),
+        None      => (input, "ms"),
+    };
+
+    let amount: u64 = number.parse()
+        .map_err(|_| format!("Invalid duration: {:?}", input))?;
+
+    let duration = match unit.trim() {
+        "ms" => Some(Duration::from_millis(amount)),
+        "s"  => Some(Duration::from_secs(amount)),
+        "m"  => amount.checked_mul(60).map(Duration::from_secs),
+        _    => return Err(format!("Invalid duration unit in {:?}, expected ms, s or m", input)),
+    };
+    duration
+        .filter(|duration| *duration <= MAX_DURATION)
+        .ok_or_else(|| format!("Duration {:?} is too long, the most is a year", input))
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use super::{ parse_duration, Debouncer, };
+    use super::super::change::{ ChangeEvent, ChangeKind, };
+
+    fn change(path: &str, kind: ChangeKind) -> ChangeEvent {
+        ChangeEvent::new(kind, PathBuf::from(path), None)
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("200ms"), Ok(Duration::from_millis(200)));
+        assert_eq!(parse_duration("150"), Ok(Duration::from_millis(150)));
+        assert_eq!(parse_duration("2s"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_duration("1m"), Ok(Duration::from_secs(60)));
+        assert!(parse_duration("fast").is_err());
+        assert!(parse_duration("10h").is_err());
+        assert!(parse_duration("18446744073709551615s").is_err());
+        assert!(parse_duration("18446744073709551615m").is_err());
+    }
+
+    #[test]
+    fn coalesces_changes() {
+        let mut debouncer = Debouncer::new(Duration::from_millis(0));
+        assert!(debouncer.timeout().is_none());
+        assert!(debouncer.take_ready().is_none());
+
+        debouncer.add(change("b.rs", ChangeKind::Modified));
+        debouncer.add(change("a.rs", ChangeKind::Modified));
+        debouncer.add(change("b.rs", ChangeKind::Modified));
+
+        let batch = debouncer.take_ready().unwrap();
+        assert_eq!(batch.latest.path, PathBuf::from("b.rs"));
+        assert_eq!(batch.changed, vec!(PathBuf::from("a.rs"), PathBuf::from("b.rs")));
+        assert!(debouncer.take_ready().is_none());
+    }
+
+    #[test]
+    fn merges_batches() {
+        let mut debouncer = Debouncer::new(Duration::from_millis(0));
+        debouncer.add(change("b.rs", ChangeKind::Modified));
+        let mut batch = debouncer.take_ready().unwrap();
+        debouncer.add(change("c.rs", ChangeKind::Removed));
+        debouncer.add(change("a.rs", ChangeKind::Modified));
+        batch.merge(debouncer.take_ready().unwrap());
+
+        assert_eq!(batch.latest.path, PathBuf::from("a.rs"));
+        assert_eq!(batch.changed, vec!(PathBuf::from("a.rs"), PathBuf::from("b.rs"), PathBuf::from("c.rs")));
+    }
+
+    #[test]
+    fn waits_for_quiet_period() {
+        let mut debouncer = Debouncer::new(Duration::from_secs(60));
+        debouncer.add(change("a.rs", ChangeKind::Modified));
+        assert!(debouncer.take_ready().is_none());
+        assert!(debouncer.timeout().unwrap() > Duration::from_secs(59));
+    }
+}
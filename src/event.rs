@@ -0,0 +1,52 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// The kinds of change that can be chosen to trigger a run, named the way
+/// `--trigger` takes them. See `ChangeKind::trigger` for which changes count as
+/// which.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EventKind {
+    /// `ChangeKind::Modified`.
+    Modified,
+    /// `ChangeKind::Removed`.
+    Removed,
+    /// `ChangeKind::Renamed`.
+    Renamed,
+    /// `ChangeKind::Attrib`.
+    Attrib,
+    /// `ChangeKind::Rescan`, which always triggers a run.
+    Rescan,
+}
+
+impl EventKind {
+    /// The kinds that trigger a run unless told otherwise.
+    pub fn defaults() -> Vec<EventKind> {
+        vec!(EventKind::Modified, EventKind::Removed, EventKind::Renamed)
+    }
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EventKind::Modified => write!(f, "modified"),
+            EventKind::Removed  => write!(f, "removed"),
+            EventKind::Renamed  => write!(f, "renamed"),
+            EventKind::Attrib   => write!(f, "attrib"),
+            EventKind::Rescan   => write!(f, "rescan"),
+        }
+    }
+}
+
+impl FromStr for EventKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<EventKind, String> {
+        match s {
+            "modified" => Ok(EventKind::Modified),
+            "removed"  => Ok(EventKind::Removed),
+            "renamed"  => Ok(EventKind::Renamed),
+            "attrib"   => Ok(EventKind::Attrib),
+            other      => Err(format!("Unknown event kind {:?}, expected modified, removed, renamed or attrib", other)),
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use globset::{ GlobBuilder, GlobSet, GlobSetBuilder, };
+use regex::Regex;
+use std::path::Path;
+
+/// Decides which changed files should trigger a run.
+///
+/// Precedence, highest first:
+///
+/// 1. A file matching any `--exclude` glob never triggers.
+/// 2. With no `--ext`, `--regex` or `--include` given, every file triggers.
+/// 3. Otherwise a file triggers if it matches any extension, the regex or any
+///    include glob.
+///
+/// Globs are matched against the path relative to its watched root and, for
+/// convenience, against the bare file name, so `*.rs` matches in any directory
+/// while `src/*.rs` only matches directly under `src`.
+pub struct Filter {
+    extensions: Vec<String>,
+    regex: Option<Regex>,
+    include_globs: Vec<String>,
+    includes: GlobSet,
+    exclude_globs: Vec<String>,
+    excludes: GlobSet,
+}
+
+impl Filter {
+    pub fn new() -> Filter {
+        Filter {
+            extensions: vec!(),
+            regex: None,
+            include_globs: vec!(),
+            includes: GlobSet::empty(),
+            exclude_globs: vec!(),
+            excludes: GlobSet::empty(),
+        }
+    }
+
+    pub fn add_extension(&mut self, ext: &str) {
+        self.extensions.push(ext.trim_start_matches('.').to_string());
+    }
+
+    pub fn set_regex(&mut self, regex: Regex) {
+        self.regex = Some(regex);
+    }
+
+    pub fn add_include(&mut self, glob: &str) -> Result<(), String> {
+        let mut globs = self.include_globs.clone();
+        globs.push(glob.to_string());
+        self.includes = build_set(&globs)?;
+        self.include_globs = globs;
+        Ok(())
+    }
+
+    pub fn add_exclude(&mut self, glob: &str) -> Result<(), String> {
+        let mut globs = self.exclude_globs.clone();
+        globs.push(glob.to_string());
+        self.excludes = build_set(&globs)?;
+        self.exclude_globs = globs;
+        Ok(())
+    }
+
+    /// Whether a changed file passes the filters.
+    pub fn matches(&self, path: &Path, root: Option<&Path>) -> bool {
+        let relative = root
+            .and_then(|root| path.strip_prefix(root).ok())
+            .unwrap_or(path);
+        let name = Path::new(path.file_name().unwrap_or_default());
+        let globbed = |set: &GlobSet| set.is_match(relative) || set.is_match(name);
+
+        if globbed(&self.excludes) {
+            return false;
+        }
+
+        if self.extensions.is_empty() && self.regex.is_none() && self.include_globs.is_empty() {
+            return true;
+        }
+
+        let ext_match = path.extension()
+            .map(|actual| self.extensions.iter().any(|ext| actual == ext.as_str()))
+            .unwrap_or(false);
+
+        let regex_match = self.regex
+            .as_ref()
+            .map(|re| re.is_match(&path.to_string_lossy()))
+            .unwrap_or(false);
+
+        ext_match || regex_match || globbed(&self.includes)
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter::new()
+    }
+}
+
+fn build_set(globs: &[String]) -> Result<GlobSet, String> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        builder.add(GlobBuilder::new(glob)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(|e| format!("Invalid glob {:?}: {}", glob, e))?);
+    }
+    builder.build()
+        .map_err(|e| format!("Error building globs: {}", e))
+}
+
+#[cfg(test)]
+mod test {
+    use regex::Regex;
+    use std::path::Path;
+    use super::Filter;
+
+    fn matches(filter: &Filter, path: &str) -> bool {
+        filter.matches(Path::new(path), Some(Path::new("/repo")))
+    }
+
+    #[test]
+    fn everything_by_default() {
+        let filter = Filter::new();
+        assert!(matches(&filter, "/repo/src/main.rs"));
+        assert!(matches(&filter, "/repo/README"));
+    }
+
+    #[test]
+    fn includes_are_alternatives() -> Result<(), String> {
+        let mut filter = Filter::new();
+        filter.add_extension("rs");
+        filter.add_extension(".toml");
+        filter.add_include("docs/**/*.md")?;
+        filter.set_regex(Regex::new("Makefile$").unwrap());
+
+        assert!(matches(&filter, "/repo/src/main.rs"));
+        assert!(matches(&filter, "/repo/Cargo.toml"));
+        assert!(matches(&filter, "/repo/docs/guide/intro.md"));
+        assert!(matches(&filter, "/repo/Makefile"));
+        assert!(!matches(&filter, "/repo/README.md"));
+        assert!(!matches(&filter, "/repo/src/main.c"));
+        Ok(())
+    }
+
+    #[test]
+    fn excludes_win() -> Result<(), String> {
+        let mut filter = Filter::new();
+        filter.add_include("*.rs")?;
+        filter.add_exclude("*_generated.rs")?;
+        filter.add_exclude("vendor/**")?;
+
+        assert!(matches(&filter, "/repo/src/lib.rs"));
+        assert!(!matches(&filter, "/repo/src/proto_generated.rs"));
+        assert!(!matches(&filter, "/repo/vendor/dep/lib.rs"));
+        assert!(Filter::new().add_include("a[").is_err());
+        Ok(())
+    }
+}
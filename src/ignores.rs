@@ -0,0 +1,184 @@
+use ignore::gitignore::{ Gitignore, GitignoreBuilder, };
+use ignore::Match;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{ Component, Path, PathBuf, };
+
+/// File names that hold ignore rules for the directory they live in.
+const IGNORE_FILES: [&str; 2] = [".gitignore", ".ignore"];
+
+/// Applies `.gitignore`, `.ignore` and global git excludes to individual paths.
+///
+/// Every directory above the path contributes its `.ignore` rules, with deeper
+/// directories taking precedence. Git's own rules only count inside a repository
+/// and below its root, the same as a walk with `ignore::WalkBuilder` would.
+/// Matchers are read lazily and cached per directory.
+pub struct IgnoreFilter {
+    global: Gitignore,
+    dirs: RefCell<HashMap<PathBuf, Gitignore>>,
+    warnings: RefCell<Vec<String>>,
+}
+
+impl IgnoreFilter {
+    pub fn new() -> IgnoreFilter {
+        let (global, err) = Gitignore::global();
+        let warnings = err
+            .map(|e| vec!(format!("could not read global git excludes: {}", e)))
+            .unwrap_or_default();
+
+        IgnoreFilter {
+            global,
+            dirs: RefCell::new(HashMap::new()),
+            warnings: RefCell::new(warnings),
+        }
+    }
+
+    /// Problems reading ignore files since this was last called.
+    pub fn take_warnings(&self) -> Vec<String> {
+        std::mem::take(&mut *self.warnings.borrow_mut())
+    }
+
+    /// Whether a path is excluded by any applicable ignore file.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let path = absolute(path);
+        if path.components().any(|c| c.as_os_str() == ".git") {
+            return true;
+        }
+
+        let repo_root = path.ancestors().skip(1).find(|dir| dir.join(".git").exists());
+        for dir in path.ancestors().skip(1) {
+            let in_repo = repo_root.map(|root| dir.starts_with(root)).unwrap_or(false);
+            match self.matched(dir, &path, is_dir, in_repo) {
+                Match::Ignore(_)    => return true,
+                Match::Whitelist(_) => return false,
+                Match::None         => (),
+            }
+        }
+
+        match repo_root {
+            Some(root) => self.global.matched_path_or_any_parents(path.strip_prefix(root).unwrap_or(&path), is_dir).is_ignore(),
+            None       => false,
+        }
+    }
+
+    /// Forget cached rules when an ignore file changes.
+    pub fn notice_change(&self, path: &Path) {
+        let is_ignore_file = path.file_name()
+            .map(|name| IGNORE_FILES.iter().any(|f| name == *f))
+            .unwrap_or(false);
+
+        if is_ignore_file {
+            if let Some(dir) = absolute(path).parent() {
+                self.dirs.borrow_mut().remove(dir);
+            }
+        }
+    }
+
+    fn matched(&self, dir: &Path, path: &Path, is_dir: bool, in_repo: bool) -> Match<()> {
+        let mut dirs = self.dirs.borrow_mut();
+        let matcher = dirs.entry(dir.to_path_buf())
+            .or_insert_with(|| build_matcher(dir, in_repo, &mut self.warnings.borrow_mut()));
+        match matcher.matched_path_or_any_parents(path, is_dir) {
+            Match::Ignore(_)    => Match::Ignore(()),
+            Match::Whitelist(_) => Match::Whitelist(()),
+            Match::None         => Match::None,
+        }
+    }
+}
+
+impl Default for IgnoreFilter {
+    fn default() -> IgnoreFilter {
+        IgnoreFilter::new()
+    }
+}
+
+/// Build the combined matcher for one directory's ignore files, leaving out
+/// git's unless the directory is part of a repository.
+fn build_matcher(dir: &Path, in_repo: bool, warnings: &mut Vec<String>) -> Gitignore {
+    let mut builder = GitignoreBuilder::new(dir);
+
+    // Later files win, so go from least to most specific.
+    let mut files = vec!();
+    if in_repo {
+        files.push(dir.join(".git").join("info").join("exclude"));
+        files.push(dir.join(".gitignore"));
+    }
+    files.push(dir.join(".ignore"));
+
+    for file in files.iter().filter(|f| f.is_file()) {
+        if let Some(e) = builder.add(file) {
+            warnings.push(format!("problem reading {}: {}", file.display(), e));
+        }
+    }
+
+    builder.build().unwrap_or_else(|e| {
+        warnings.push(format!("could not parse ignore files in {}: {}", dir.display(), e));
+        Gitignore::empty()
+    })
+}
+
+/// Make a path absolute and drop `.` and `..` from it, without touching the
+/// filesystem since the path may already be gone.
+fn absolute(path: &Path) -> PathBuf {
+    let joined = std::env::current_dir()
+        .map(|cwd| cwd.join(path))
+        .unwrap_or_else(|_| path.to_path_buf());
+
+    let mut normal = PathBuf::new();
+    for component in joined.components() {
+        match component {
+            Component::CurDir    => (),
+            Component::ParentDir => { normal.pop(); },
+            component            => normal.push(component),
+        }
+    }
+    normal
+}
+
+#[cfg(test)]
+mod test {
+    use std::error::Error;
+    use std::fs::{ create_dir_all, write, };
+    use tempfile::tempdir;
+    use super::IgnoreFilter;
+
+    #[test]
+    fn nested_rules() -> Result<(), Box<dyn Error>> {
+        let dir = tempdir()?;
+        let root = dir.path();
+        create_dir_all(root.join(".git"))?;
+        create_dir_all(root.join("src/gen"))?;
+        write(root.join(".gitignore"), "target/\n*.log\n")?;
+        write(root.join("src/.ignore"), "gen/\n!keep.log\n")?;
+
+        let filter = IgnoreFilter::new();
+        assert!(filter.is_ignored(&root.join("target/debug/out.o"), false));
+        assert!(filter.is_ignored(&root.join("target"), true));
+        assert!(filter.is_ignored(&root.join("build.log"), false));
+        assert!(filter.is_ignored(&root.join("src/gen/mod.rs"), false));
+        assert!(filter.is_ignored(&root.join(".git/index"), false));
+        assert!(!filter.is_ignored(&root.join("src/keep.log"), false));
+        assert!(!filter.is_ignored(&root.join("src/main.rs"), false));
+
+        write(root.join("src/.ignore"), "main.rs\n")?;
+        filter.notice_change(&root.join("src/.ignore"));
+        assert!(filter.is_ignored(&root.join("src/main.rs"), false));
+
+        // Outside a repository only .ignore files count.
+        let dir = tempdir()?;
+        let root = dir.path();
+        create_dir_all(root.join("watched"))?;
+        write(root.join(".gitignore"), "*.txt\n")?;
+        write(root.join(".ignore"), "*.tmp\n")?;
+        assert!(!filter.is_ignored(&root.join("watched/a.txt"), false));
+        assert!(filter.is_ignored(&root.join("watched/a.tmp"), false));
+
+        // A sibling reached through .. doesn't pick up rules from where it was
+        // reached from.
+        create_dir_all(root.join("watched/sub"))?;
+        write(root.join("watched/sub/.ignore"), "*.rs\n")?;
+        assert!(!filter.is_ignored(&root.join("watched/sub/../main.rs"), false));
+        assert!(filter.is_ignored(&root.join("watched/sub/./main.rs"), false));
+        Ok(())
+    }
+}
@@ -0,0 +1,17 @@
+//! Watch directory trees and get filtered, debounced batches of changed files.
+//!
+//! Start with a `WatcherBuilder`, then iterate the `Watcher` it builds.
+
+pub mod backend;
+pub mod change;
+pub mod debounce;
+pub mod event;
+pub mod filter;
+pub mod ignores;
+pub mod watcher;
+pub mod watches;
+
+pub use change::{ ChangeEvent, ChangeKind, };
+pub use debounce::Batch;
+pub use event::EventKind;
+pub use watcher::{ Update, Watcher, WatcherBuilder, };
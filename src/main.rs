@@ -1,24 +1,38 @@
-mod fwatch;
+#[allow(dead_code)]
 mod pager;
+mod completions;
+mod config;
+mod notify;
 mod pager2;
+mod policy;
+mod process;
+mod report;
+mod rule;
+mod runtime;
+mod template;
 
-use clap::{App, AppSettings, Arg, Shell, SubCommand, };
-use fwatch::Runtime;
-use regex::Regex;
+use clap::{App, AppSettings, Arg, ArgMatches, Shell, SubCommand, };
+use config::Settings;
+use fwatch::Watcher;
+use runtime::Runtime;
+use std::path::{ Path, PathBuf, };
 
 enum CommandInput {
-    Run(Runtime),
-    Completions,
+    Run(Box<Runtime>, Box<Watcher>),
+    Completions(Shell),
+    /// Nothing left to do.
+    Done,
 }
 
-fn main() -> Result<(), Box<std::error::Error>> {
+fn main() -> Result<(), Box<dyn std::error::Error>> {
     match parse_cli()? {
-        CommandInput::Run(runtime) => {
-            runtime.run()?;
+        CommandInput::Run(runtime, watcher) => {
+            runtime.run(*watcher)?;
         },
-        CommandInput::Completions  => {
-            build_cli().gen_completions_to("fwatch", Shell::Bash, &mut std::io::stdout());
+        CommandInput::Completions(shell) => {
+            completions::generate(build_cli(), shell, &mut std::io::stdout())?;
         },
+        CommandInput::Done               => (),
     };
 
     Ok(())
@@ -30,63 +44,273 @@ fn build_cli() -> App<'static, 'static> {
         .about("Watch files")
         .setting(AppSettings::SubcommandRequiredElseHelp)
         .subcommand(SubCommand::with_name("completions")
-            .about("Generates bash completions"))
+            .about("Generates shell completions")
+            .arg(Arg::with_name("shell")
+                 .possible_values(&Shell::variants())
+                 .default_value("bash")
+                 .help("The shell to generate completions for")))
+        .subcommand(SubCommand::with_name("config")
+            .about("Inspect the fwatch.toml config file")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("check")
+                .about("Check that the config file is valid")
+                .arg(config_arg())))
+        .subcommand(SubCommand::with_name("profiles")
+            .about("List the profiles in the config file")
+            .arg(config_arg())
+            .arg(Arg::with_name("names")
+                 .long("names")
+                 .hidden(true)
+                 .help("Only print the names, for shell completions")))
         .subcommand(SubCommand::with_name("run")
                     .arg(Arg::with_name("dirs")
                          .help("Directories to monitor for changes recursively")
                          .multiple(true)
-                         .required(true)
                          .min_values(1))
+                    .arg(config_arg())
+                    .arg(Arg::with_name("profile")
+                         .long("profile")
+                         .value_name("name")
+                         .takes_value(true)
+                         .help("Use a profile from the config file, see 'fwatch profiles'"))
                     .arg(Arg::with_name("pager")
                          .long("pager")
                          .short("p")
                          .help("Run with a pager"))
+                    .arg(negation("no-pager", "pager", "Run without a pager, even if the config file asks for one"))
+                    .arg(Arg::with_name("shell")
+                         .long("shell")
+                         .short("s")
+                         .help("Run the command as a script with $SHELL -c, so '&&', pipes and variables work. \
+                                Substituted paths are quoted for a POSIX shell and '{+}' may appear anywhere"))
+                    .arg(negation("no-shell", "shell", "Don't run the command as a script, even if the config file says to"))
                     .arg(Arg::with_name("ext")
                          .long("ext")
                          .short("e")
                          .value_name("extension")
                          .takes_value(true)
-                         .help("filter files to a file extension"))
+                         .multiple(true)
+                         .number_of_values(1)
+                         .help("filter files to a file extension, may be repeated"))
                     .arg(Arg::with_name("regex")
                          .long("regex")
-                         .value_name("extension")
+                         .value_name("regex")
                          .takes_value(true)
                          .help("filter files by regex"))
+                    .arg(Arg::with_name("include")
+                         .long("include")
+                         .short("i")
+                         .value_name("glob")
+                         .takes_value(true)
+                         .multiple(true)
+                         .number_of_values(1)
+                         .help("filter files by glob, may be repeated. Files matching any --ext, --regex or --include trigger a run"))
+                    .arg(Arg::with_name("exclude")
+                         .long("exclude")
+                         .short("x")
+                         .value_name("glob")
+                         .takes_value(true)
+                         .multiple(true)
+                         .number_of_values(1)
+                         .help("never run for files matching this glob, may be repeated. Takes precedence over every other filter, in every rule"))
+                    .arg(Arg::with_name("trigger")
+                         .long("trigger")
+                         .short("t")
+                         .value_name("event")
+                         .takes_value(true)
+                         .multiple(true)
+                         .number_of_values(1)
+                         .possible_values(&["modified", "removed", "renamed", "attrib"])
+                         .help("Kind of change that triggers a run, may be repeated. Defaults to modified, removed and renamed"))
+                    .arg(Arg::with_name("initial")
+                         .long("initial")
+                         .alias("run-on-start")
+                         .help("Run the command once at startup, with the watched directories standing in for changed files"))
+                    .arg(negation("no-initial", "initial", "Don't run the command at startup, even if the config file says to"))
+                    .arg(Arg::with_name("no-ignore")
+                         .long("no-ignore")
+                         .help("Don't respect .gitignore, .ignore or global git exclude files"))
+                    .arg(Arg::with_name("debounce")
+                         .long("debounce")
+                         .short("d")
+                         .value_name("duration")
+                         .takes_value(true)
+                         .help("Wait for changes to settle for this long (e.g. 200ms, 1s) before running the command"))
+                    .arg(Arg::with_name("poll")
+                         .long("poll")
+                         .help("Scan for changes periodically instead of using inotify. Works on network and FUSE filesystems"))
+                    .arg(negation("no-poll", "poll", "Use inotify even if the config file says to poll"))
+                    .arg(Arg::with_name("fanotify")
+                         .long("fanotify")
+                         .conflicts_with("poll")
+                         .help("Watch whole filesystems with fanotify instead of a watch per directory. \
+                                Needs root, falls back to inotify otherwise"))
+                    .arg(negation("no-fanotify", "fanotify", "Use a watch per directory even if the config file says to use fanotify"))
+                    .arg(Arg::with_name("poll-interval")
+                         .long("poll-interval")
+                         .value_name("duration")
+                         .takes_value(true)
+                         .requires("poll")
+                         .help("How often to scan when polling (e.g. 500ms, 2s). Defaults to 1s"))
+                    .arg(Arg::with_name("on-busy")
+                         .long("on-busy")
+                         .value_name("policy")
+                         .takes_value(true)
+                         .possible_values(&["restart", "queue", "ignore", "parallel"])
+                         .help("What to do when files change while the command is still running: restart it (the default), \
+                                queue one more run for after it finishes, ignore the changes, or run in parallel"))
+                    .arg(Arg::with_name("jobs")
+                         .long("jobs")
+                         .short("j")
+                         .value_name("n")
+                         .takes_value(true)
+                         .help("Most runs at once with --on-busy parallel, further changes are queued. Defaults to 4"))
+                    .arg(Arg::with_name("signal")
+                         .long("signal")
+                         .value_name("signal")
+                         .takes_value(true)
+                         .help("Signal sent to the command's process group to stop it, e.g. INT or HUP. Defaults to TERM"))
+                    .arg(Arg::with_name("kill-timeout")
+                         .long("kill-timeout")
+                         .value_name("duration")
+                         .takes_value(true)
+                         .help("How long the command gets to exit after --signal before it is killed. Defaults to 5s"))
+                    .arg(Arg::with_name("then")
+                         .long("then")
+                         .value_name("script")
+                         .takes_value(true)
+                         .multiple(true)
+                         .number_of_values(1)
+                         .help("A shell script to run after the command succeeds, taking the same placeholders. \
+                                Repeat for more steps, each runs only if everything before it succeeded"))
+                    .arg(Arg::with_name("on-success")
+                         .long("on-success")
+                         .value_name("script")
+                         .takes_value(true)
+                         .help("A shell script to run after every step succeeded"))
+                    .arg(Arg::with_name("on-failure")
+                         .long("on-failure")
+                         .value_name("script")
+                         .takes_value(true)
+                         .help("A shell script to run after a step failed"))
+                    .arg(Arg::with_name("notify")
+                         .long("notify")
+                         .value_name("how")
+                         .takes_value(true)
+                         .help("Notify when a run finishes: 'bell', 'osc9' or 'osc777' terminal notifications, \
+                                or a notify-send compatible command, called with a title and message"))
+                    .arg(Arg::with_name("notify-success")
+                         .long("notify-success")
+                         .value_name("how")
+                         .takes_value(true)
+                         .help("Like --notify, only for runs that succeeded"))
+                    .arg(Arg::with_name("notify-failure")
+                         .long("notify-failure")
+                         .value_name("how")
+                         .takes_value(true)
+                         .help("Like --notify, only for runs that failed"))
+                    .arg(Arg::with_name("rule")
+                         .long("rule")
+                         .value_name("rule")
+                         .takes_value(true)
+                         .multiple(true)
+                         .number_of_values(1)
+                         .help("Also run a shell script for files matching some globs, written 'GLOBS [OPTIONS]: SCRIPT', \
+                                e.g. '*.scss debounce=500ms on-busy=queue: sass build'. May be repeated. Each rule runs \
+                                independently, taking --trigger, --exclude, --debounce and --on-busy as defaults"))
                     .arg(Arg::with_name("command")
-                         .help("The template command to run on changes. Allows for a single placeholder '{}' to input the file name into.")
+                         .help("The template command to run on changes. Placeholders: '{}' for the changed file, \
+                                '{dir}', '{name}', '{stem}', '{ext}', '{rel}' (relative to the watched root) and '{event}'. \
+                                An argument of '{+}' passes every changed file. Use '{{' and '}}' for literal braces.")
                          .multiple(true)
                          .min_values(1)
-                         .required(true)
                          .last(true)))
 }
 
+fn config_arg() -> Arg<'static, 'static> {
+    Arg::with_name("config")
+        .long("config")
+        .short("c")
+        .value_name("path")
+        .takes_value(true)
+        .help("Read settings from this file instead of the nearest fwatch.toml in the current directory or above. \
+               Flags given on the command line take precedence")
+}
+
+/// `--no-<flag>`, for turning off a flag the config file turns on. Whichever of
+/// the two comes last wins.
+fn negation(name: &'static str, flag: &'static str, help: &'static str) -> Arg<'static, 'static> {
+    Arg::with_name(name)
+        .long(name)
+        .overrides_with(flag)
+        .help(help)
+}
+
+/// The config file a subcommand is about, which has to exist.
+fn config_file(matches: &ArgMatches) -> Result<PathBuf, String> {
+    config::locate(matches.value_of_os("config").map(Path::new))?
+        .ok_or_else(|| format!("No {} found in the current directory or above", config::FILE_NAME))
+}
+
 fn parse_cli() -> Result<CommandInput, String> {
     match build_cli().get_matches().subcommand() {
-        ("completions", _) => Ok(CommandInput::Completions),
+        ("completions", Some(matches)) => Ok(CommandInput::Completions(matches
+            .value_of("shell")
+            .unwrap_or("bash")
+            .parse()?)),
+        ("config", Some(matches)) => match matches.subcommand() {
+            ("check", Some(matches)) => {
+                let path = config_file(matches)?;
+                Settings::load(&path)?.check_file()?;
+                println!("{} is valid", path.display());
+                Ok(CommandInput::Done)
+            },
+            (_, _) => unreachable!(),
+        },
+        ("profiles", Some(matches)) => {
+            let path = config_file(matches)?;
+            let settings = Settings::load(&path)?;
+            let profiles = settings.profiles();
+            if matches.is_present("names") {
+                for (name, _) in profiles {
+                    println!("{}", name);
+                }
+                return Ok(CommandInput::Done);
+            }
+            if profiles.is_empty() {
+                println!("No profiles in {}", path.display());
+            }
+            let width = profiles.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+            for (name, description) in profiles {
+                println!("{}", format!("{:width$}  {}", name, description.unwrap_or(""), width = width).trim_end());
+            }
+            Ok(CommandInput::Done)
+        },
         ("run", Some(matches)) => {
-            let mut runtime = Runtime::new(
-                matches.values_of("command")
-                .ok_or_else(|| "No template provided")?
-                .map(str::to_string)
-                .collect())?;
-
-
-            matches.value_of("ext")
-                .map(|e| runtime.set_extension(e.to_string()));
+            let mut settings = Settings::from_matches(matches)?;
+            match (config::locate(matches.value_of_os("config").map(Path::new))?, matches.value_of("profile")) {
+                (Some(path), Some(profile)) => settings = settings.or(Settings::load(&path)?.with_profile(profile)?),
+                (Some(path), None)          => settings = settings.or(Settings::load(&path)?),
+                (None, Some(_))             => return Err(format!("--profile needs a {} to read profiles from", config::FILE_NAME)),
+                (None, None)                => (),
+            }
 
-            matches.value_of("regex")
-                .map(|re| runtime.set_regex(Regex::new(re).expect("Invalid regex")));
+            let mut runtime = settings.runtime()?;
+            let watcher = settings.watcher()?.build()?;
 
-            matches.values_of("dirs")
-                .ok_or_else(|| "No dirs provided")?
-                .for_each(|dir| {
-                    runtime.watch_directories(&dir)
-                        .expect("Error watching directories");
-                });
+            let stats = watcher.watch_stats();
+            if stats.missed() > 0 {
+                println!("Warning, only watching {} of {} directories, changes in the other {} will be missed",
+                         stats.watched, stats.requested, stats.missed());
+            }
+            else {
+                println!("Watching {} directories", stats.watched);
+            }
 
-            runtime.use_pager(matches.is_present("pager"));
+            runtime.use_pager(settings.pager())
+                .map_err(|e| format!("Error starting pager: {}", e))?;
 
-            Ok(CommandInput::Run(runtime))
+            Ok(CommandInput::Run(Box::new(runtime), Box::new(watcher)))
         }
         (_, _) => unimplemented!(),
     }
@@ -0,0 +1,111 @@
+use std::io::{ stdout, Write, };
+use std::process::Command;
+use std::str::FromStr;
+use super::report::RunReport;
+
+/// How to tell the user a run finished.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Notifier {
+    /// Ring the terminal bell.
+    Bell,
+    /// OSC 9 notification, as understood by iTerm2, kitty, WezTerm and others.
+    Osc9,
+    /// OSC 777 notification, as understood by urxvt, foot and VTE terminals.
+    Osc777,
+    /// A `notify-send` compatible program, called with a title and body.
+    Command(String),
+}
+
+impl FromStr for Notifier {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Notifier, String> {
+        match input {
+            "bell"   => Ok(Notifier::Bell),
+            "osc9"   => Ok(Notifier::Osc9),
+            "osc777" => Ok(Notifier::Osc777),
+            ""       => Err("Expected bell, osc9, osc777 or a command to notify with".to_string()),
+            command  => Ok(Notifier::Command(command.to_string())),
+        }
+    }
+}
+
+impl Notifier {
+    /// Send a notification, without waiting for a notification command to finish.
+    pub fn notify(&self, title: &str, body: &str) -> Result<(), String> {
+        if let Notifier::Command(command) = self {
+            let mut child = Command::new(command)
+                .args([title, body])
+                .spawn()
+                .map_err(|e| format!("Error running {:?}: {}", command, e))?;
+            std::thread::spawn(move || child.wait());
+            return Ok(());
+        }
+
+        let mut stdout = stdout();
+        write!(stdout, "{}", self.escape(title, body).unwrap_or_default())
+            .and_then(|_| stdout.flush())
+            .map_err(|e| format!("Error writing notification: {}", e))
+    }
+
+    /// What to write to the terminal, unless this runs a command instead.
+    fn escape(&self, title: &str, body: &str) -> Option<String> {
+        match self {
+            Notifier::Bell       => Some("\x07".to_string()),
+            Notifier::Osc9       => Some(format!("\x1b]9;{}: {}\x07", sanitize(title), sanitize(body))),
+            Notifier::Osc777     => Some(format!("\x1b]777;notify;{};{}\x07", sanitize(title).replace(';', ","), sanitize(body))),
+            Notifier::Command(_) => None,
+        }
+    }
+}
+
+/// Keep control characters from ending the escape sequence early.
+fn sanitize(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect()
+}
+
+/// Which notifier, if any, to use for each outcome.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Notifications {
+    pub success: Option<Notifier>,
+    pub failure: Option<Notifier>,
+}
+
+impl Notifications {
+    /// Notify about a finished run, as configured for how it went.
+    pub fn notify(&self, report: &RunReport) -> Result<(), String> {
+        let (notifier, title) = if report.success() {
+            (&self.success, "fwatch: succeeded")
+        }
+        else {
+            (&self.failure, "fwatch: failed")
+        };
+        match notifier {
+            Some(notifier) => notifier.notify(title, &report.to_string()),
+            None           => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Notifier;
+
+    #[test]
+    fn parsing() {
+        assert_eq!(Ok(Notifier::Bell), "bell".parse());
+        assert_eq!(Ok(Notifier::Osc777), "osc777".parse());
+        assert_eq!(Ok(Notifier::Command("notify-send".to_string())), "notify-send".parse());
+        assert!("".parse::<Notifier>().is_err());
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(Some("\x1b]9;fwatch: failed: exit 1\x07".to_string()),
+                   Notifier::Osc9.escape("fwatch", "failed: exit\x071"));
+        assert_eq!(Some("\x1b]777;notify;a,b;c;d\x07".to_string()), Notifier::Osc777.escape("a;b", "c;d"));
+        assert_eq!(None, Notifier::Command("notify-send".to_string()).escape("a", "b"));
+    }
+}
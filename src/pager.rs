@@ -16,10 +16,10 @@ impl Pager {
 
     pub fn start(&mut self) -> Result<PipeWriter, String> {
         let (input, output) = pipe()
-            .map_err(|e| format!("Pipe creation error: {}", e.to_string()))?;
+            .map_err(|e| format!("Pipe creation error: {}", e))?;
 
         let mut c = Command::new("less");
-        c.args(vec!(
+        c.args([
                 "--chop-long-lines",
                 "--raw-control-chars",
                 "--no-init",
@@ -27,7 +27,7 @@ impl Pager {
                 "--CLEAR-SCREEN",
                 "--tilde",
                 "--mouse",
-        ));
+        ]);
         c.stdin(input);
         let proc = SharedChild::spawn(&mut c).unwrap();
         let started = Arc::new(proc);
@@ -5,6 +5,7 @@ use std::sync::{Mutex, RwLock};
 use termion::event::Key;
 use termion::raw::IntoRawMode;
 
+#[allow(dead_code)]
 enum InputState {
     SearchPrompt(String),
     CommandPrompt(String),
@@ -16,20 +17,23 @@ enum InputState {
 pub struct Pager2 {
     offset: RwLock<(u16, u16)>,
     lines: RwLock<Vec<String>>,
+    /// How the last run went, and whether it succeeded.
+    status: RwLock<Option<(String, bool)>>,
     stdout: Mutex<termion::raw::RawTerminal<Stdout>>,
     input_state: InputState,
 }
 
 impl Drop for Pager2 {
     fn drop(&mut self) {
-        write!(stdout(), "{}", termion::cursor::Show).unwrap();
+        print!("{}", termion::cursor::Show);
     }
 }
 
 impl Pager2 {
-    pub fn new() -> Result<Pager2, Box<Error>> {
+    pub fn new() -> Result<Pager2, Box<dyn Error>> {
         Ok(Pager2 {
             lines: RwLock::new(vec!()),
+            status: RwLock::new(None),
             stdout: Mutex::new(stdout().into_raw_mode().unwrap()),
             offset: RwLock::new((0, 0)),
             input_state: InputState::Free,
@@ -43,6 +47,14 @@ impl Pager2 {
         self.draw();
     }
 
+    /// Show how the last run went in the status bar, green or red.
+    pub fn set_status(&self, text: &str, success: bool) {
+        if let Ok(mut status) = self.status.write() {
+            *status = Some((text.to_owned(), success));
+        }
+        self.draw();
+    }
+
     fn draw(&self) {
         self.draw_base();
     }
@@ -50,7 +62,7 @@ impl Pager2 {
     fn draw_base(&self) {
         let mut buf = String::with_capacity(300);
         let size = termion::terminal_size().unwrap();
-        let offset = self.offset.read().unwrap().clone();
+        let offset = *self.offset.read().unwrap();
 
         write!(buf, "{}{}", termion::cursor::Goto(1, 1), termion::clear::All).unwrap();
 
@@ -58,10 +70,21 @@ impl Pager2 {
             write!(buf, "{}{}", termion::cursor::Goto(1, 1), termion::clear::All).unwrap();
             if let Some(range) = lines.get(offset.1 as usize .. std::cmp::min(lines.len(), size.1 as usize - 1) + offset.1 as usize) {
                 for line in range.iter() {
-                    write!(buf, "{}{}", line, "\r").unwrap();
+                    write!(buf, "{}\r", line).unwrap();
                 }
             }
 
+            if let Some((text, success)) = &*self.status.read().unwrap() {
+                let bg = if *success { termion::color::Rgb(0, 160, 0) } else { termion::color::Rgb(200, 0, 0) };
+                write!(buf, "{start}{fg}{bg}{text}{fg_reset}{bg_reset}",
+                       bg = termion::color::Bg(bg),
+                       fg = termion::color::Fg(termion::color::Rgb(255, 255, 255)),
+                       text = text,
+                       fg_reset = termion::color::Fg(termion::color::Reset),
+                       bg_reset = termion::color::Bg(termion::color::Reset),
+                       start = termion::cursor::Goto(1, size.1)).unwrap();
+            }
+
             let scroller_text = format!("{numerator}/{denominator}",
                                         numerator = offset.1,
                                         denominator = lines.len());
@@ -104,7 +127,7 @@ impl Pager2 {
                 println!("Handling {:?}", c);
             },
         }
-        return InputState::Free;
+        InputState::Free
     }
 
     pub fn run(&self) {
@@ -133,6 +156,9 @@ impl Pager2 {
         if let Ok(mut lines) = self.lines.write() {
             lines.clear();
         }
+        if let Ok(mut status) = self.status.write() {
+            *status = None;
+        }
 
         self.slide((0, 0));
     }
@@ -0,0 +1,41 @@
+use std::str::FromStr;
+
+/// How many runs `--on-busy parallel` allows at once unless told otherwise.
+pub const DEFAULT_JOBS: usize = 4;
+
+/// What to do with changes that arrive while the command is still running.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OnBusy {
+    /// Kill the running command and start over.
+    Restart,
+    /// Let it finish, then run once more for everything that changed meanwhile.
+    Queue,
+    /// Drop the changes.
+    Ignore,
+    /// Start another run alongside, up to this many at once, queueing past that.
+    Parallel(usize),
+}
+
+impl OnBusy {
+    /// How many runs may be going at once.
+    pub fn capacity(self) -> usize {
+        match self {
+            OnBusy::Parallel(jobs) => jobs.max(1),
+            _                      => 1,
+        }
+    }
+}
+
+impl FromStr for OnBusy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OnBusy, String> {
+        match s {
+            "restart"  => Ok(OnBusy::Restart),
+            "queue"    => Ok(OnBusy::Queue),
+            "ignore"   => Ok(OnBusy::Ignore),
+            "parallel" => Ok(OnBusy::Parallel(DEFAULT_JOBS)),
+            other      => Err(format!("Unknown policy {:?}, expected restart, queue, ignore or parallel", other)),
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use libc::c_int;
+use os_pipe::pipe;
+use shared_child::SharedChild;
+use std::io::{ Error, Read, };
+use std::os::unix::io::IntoRawFd;
+use std::sync::atomic::{ AtomicI32, Ordering, };
+use std::time::{ Duration, Instant, };
+
+const SIGNALS: &[(&str, c_int)] = &[
+    ("HUP",  libc::SIGHUP),
+    ("INT",  libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("TERM", libc::SIGTERM),
+];
+
+/// How often to check whether a process group is gone.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Parse a signal given as `TERM`, `SIGTERM` or a number.
+pub fn parse_signal(input: &str) -> Result<c_int, String> {
+    if let Ok(number) = input.parse() {
+        return Ok(number);
+    }
+
+    let name = input.to_uppercase();
+    let name = name.trim_start_matches("SIG");
+    SIGNALS.iter()
+        .find(|(known, _)| *known == name)
+        .map(|(_, signal)| *signal)
+        .ok_or_else(|| format!("Unknown signal {:?}, expected one of HUP, INT, QUIT, KILL, USR1, USR2, TERM or a number", input))
+}
+
+/// How to stop a run. Every command is started in its own process group, so
+/// whatever it spawned is stopped along with it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stopper {
+    /// Sent to the whole group first.
+    pub signal: c_int,
+    /// How long the group gets to exit before it is sent `SIGKILL`.
+    pub timeout: Duration,
+}
+
+impl Default for Stopper {
+    fn default() -> Stopper {
+        Stopper {
+            signal: libc::SIGTERM,
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl Stopper {
+    /// Signal the child's process group and wait for all of it to exit,
+    /// escalating to `SIGKILL` after the timeout.
+    pub fn stop(&self, child: &SharedChild) -> Result<(), String> {
+        let group = child.id() as libc::pid_t;
+        signal_group(group, self.signal)?;
+        // A stopped group would hold on to the signal until continued.
+        signal_group(group, libc::SIGCONT)?;
+        if wait_for_group(child, group, self.timeout) {
+            return Ok(());
+        }
+
+        signal_group(group, libc::SIGKILL)?;
+        wait_for_group(child, group, Duration::from_secs(1));
+        Ok(())
+    }
+}
+
+fn signal_group(group: libc::pid_t, signal: c_int) -> Result<(), String> {
+    if unsafe { libc::kill(-group, signal) } < 0 {
+        let e = Error::last_os_error();
+        // Nothing left to signal.
+        if e.raw_os_error() != Some(libc::ESRCH) {
+            return Err(format!("Error signalling process group {}: {}", group, e));
+        }
+    }
+    Ok(())
+}
+
+/// Whether the group emptied out in time. The leader is reaped along the way,
+/// otherwise it would linger as a zombie and keep the group alive.
+fn wait_for_group(child: &SharedChild, group: libc::pid_t, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let _ = child.try_wait();
+        if !group_alive(group) {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Whether anything in the group is still running. Orphaned members that already
+/// exited count as gone, even if nobody has reaped them yet.
+fn group_alive(group: libc::pid_t) -> bool {
+    if unsafe { libc::kill(-group, 0) } < 0 {
+        return false;
+    }
+
+    let entries = match std::fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_)      => return true,
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| std::fs::read_to_string(entry.path().join("stat")).ok())
+        .any(|stat| {
+            // `pid (comm) state ppid pgrp ...`, where comm may hold anything.
+            let fields = stat.rsplit(')').next().unwrap_or("").split_whitespace().collect::<Vec<_>>();
+            fields.len() > 2 && fields[0] != "Z" && fields[2] == group.to_string()
+        })
+}
+
+/// Write end of the pipe the signal handler pokes.
+static INTERRUPT_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn on_interrupt(_: c_int) {
+    let fd = INTERRUPT_PIPE.load(Ordering::Relaxed);
+    if fd >= 0 {
+        unsafe { libc::write(fd, [0u8].as_ptr() as *const libc::c_void, 1) };
+    }
+}
+
+/// Call `notify` from a background thread on `SIGINT`, `SIGTERM` or `SIGHUP`,
+/// instead of dying and leaving the command's process group running.
+pub fn watch_interrupts(notify: impl Fn() + Send + 'static) -> Result<(), String> {
+    let (mut reader, writer) = pipe()
+        .map_err(|e| format!("Error creating signal pipe: {}", e))?;
+    INTERRUPT_PIPE.store(writer.into_raw_fd(), Ordering::Relaxed);
+
+    for signal in &[libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+        unsafe { libc::signal(*signal, on_interrupt as extern "C" fn(c_int) as libc::sighandler_t) };
+    }
+
+    std::thread::spawn(move || {
+        let mut byte = [0u8];
+        while let Ok(1) = reader.read(&mut byte) {
+            notify();
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use shared_child::SharedChild;
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+    use std::time::{ Duration, Instant, };
+    use super::{ parse_signal, Stopper, };
+
+    #[test]
+    fn signals() {
+        assert_eq!(Ok(libc::SIGTERM), parse_signal("TERM"));
+        assert_eq!(Ok(libc::SIGINT), parse_signal("sigint"));
+        assert_eq!(Ok(9), parse_signal("9"));
+        assert!(parse_signal("NOPE").is_err());
+    }
+
+    #[test]
+    fn stops_whole_group() -> Result<(), Box<dyn std::error::Error>> {
+        // The grandchild ignores SIGTERM, so it takes a SIGKILL.
+        let child = SharedChild::spawn(Command::new("sh")
+                                       .args(["-c", "sh -c 'trap \"\" TERM; sleep 10' & wait"])
+                                       .process_group(0))?;
+        std::thread::sleep(Duration::from_millis(100));
+        let group = child.id() as libc::pid_t;
+
+        let started = Instant::now();
+        Stopper { signal: libc::SIGTERM, timeout: Duration::from_millis(200) }.stop(&child)?;
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert!(!super::group_alive(group));
+        Ok(())
+    }
+
+    #[test]
+    fn stops_stopped_group() -> Result<(), Box<dyn std::error::Error>> {
+        let child = SharedChild::spawn(Command::new("sleep").arg("10").process_group(0))?;
+        unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGSTOP) };
+
+        let started = Instant::now();
+        Stopper { signal: libc::SIGTERM, timeout: Duration::from_secs(5) }.stop(&child)?;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+/// How a finished run went.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RunReport {
+    /// The most recently changed file, which set the run off.
+    pub trigger: PathBuf,
+    pub status: ExitStatus,
+    /// Wall time from starting the command until it exited.
+    pub duration: Duration,
+}
+
+impl RunReport {
+    pub fn success(&self) -> bool {
+        self.status.success()
+    }
+
+    /// `exit 1`, or `signal 9` when it was killed.
+    pub fn outcome(&self) -> String {
+        match (self.status.code(), self.status.signal()) {
+            (Some(code), _)   => format!("exit {}", code),
+            (None, Some(sig)) => format!("signal {}", sig),
+            (None, None)      => "unknown status".to_string(),
+        }
+    }
+
+    /// Describes the run to hooks through their environment. Like a shell, a
+    /// run killed by a signal gets an exit code of 128 plus the signal.
+    pub fn env(&self) -> Vec<(&'static str, String)> {
+        let code = self.status.code()
+            .or_else(|| self.status.signal().map(|signal| 128 + signal))
+            .unwrap_or(-1);
+        vec!(
+            ("FWATCH_STATUS", if self.success() { "success" } else { "failure" }.to_string()),
+            ("FWATCH_EXIT_CODE", code.to_string()),
+            ("FWATCH_DURATION_MS", self.duration.as_millis().to_string()),
+            ("FWATCH_TRIGGER", self.trigger.to_string_lossy().into_owned()),
+        )
+    }
+}
+
+impl fmt::Display for RunReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} after {:.2}s, triggered by {}",
+               if self.success() { "Succeeded," } else { "Failed," },
+               self.outcome(),
+               self.duration.as_secs_f64(),
+               self.trigger.display())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::process::ExitStatusExt;
+    use std::path::PathBuf;
+    use std::process::ExitStatus;
+    use std::time::Duration;
+    use super::RunReport;
+
+    #[test]
+    fn formatting() {
+        let mut report = RunReport {
+            trigger: PathBuf::from("src/main.rs"),
+            status: ExitStatus::from_raw(0),
+            duration: Duration::from_millis(1500),
+        };
+        assert!(report.success());
+        assert_eq!("Succeeded, exit 0 after 1.50s, triggered by src/main.rs", report.to_string());
+
+        report.status = ExitStatus::from_raw(2 << 8);
+        assert_eq!("Failed, exit 2 after 1.50s, triggered by src/main.rs", report.to_string());
+
+        report.status = ExitStatus::from_raw(libc::SIGKILL);
+        assert_eq!("signal 9", report.outcome());
+        assert!(!report.success());
+        assert_eq!(vec!(
+            ("FWATCH_STATUS", "failure".to_string()),
+            ("FWATCH_EXIT_CODE", "137".to_string()),
+            ("FWATCH_DURATION_MS", "1500".to_string()),
+            ("FWATCH_TRIGGER", "src/main.rs".to_string()),
+        ), report.env());
+    }
+}
@@ -0,0 +1,134 @@
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::time::Duration;
+use fwatch::debounce::parse_duration;
+use super::policy::OnBusy;
+
+/// A `--rule`, written `GLOBS [OPTIONS]: SCRIPT`.
+///
+/// Globs are separated by spaces, or by commas outside of `{a,b}` alternatives.
+/// Options are `debounce=<duration>`
+/// and `on-busy=<policy>`, defaulting to `--debounce` and `--on-busy`. In
+/// `fwatch.toml` each is a `[[rule]]` table with the same keys.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "RuleTable")]
+pub struct RuleSpec {
+    pub globs: Vec<String>,
+    pub debounce: Option<Duration>,
+    pub on_busy: Option<OnBusy>,
+    /// Run with `$SHELL -c`, like `--shell`.
+    pub script: String,
+}
+
+impl FromStr for RuleSpec {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<RuleSpec, String> {
+        let colon = input.find(':')
+            .ok_or_else(|| format!("Expected 'GLOBS: SCRIPT' in rule {:?}", input))?;
+        let (head, script) = (&input[..colon], input[colon + 1..].trim());
+
+        let mut spec = RuleSpec {
+            globs: vec!(),
+            debounce: None,
+            on_busy: None,
+            script: script.to_string(),
+        };
+        for word in words(head) {
+            match word.split_once('=') {
+                Some(("debounce", window)) => spec.debounce = Some(parse_duration(window)?),
+                Some(("on-busy", policy))  => spec.on_busy = Some(policy.parse()?),
+                Some((option, _))          => return Err(format!("Unknown option {:?} in rule {:?}, expected debounce or on-busy", option, input)),
+                None                       => spec.globs.push(word.to_string()),
+            }
+        }
+
+        if spec.globs.is_empty() || spec.script.is_empty() {
+            return Err(format!("Expected 'GLOBS: SCRIPT' in rule {:?}", input));
+        }
+        Ok(spec)
+    }
+}
+
+/// Split globs and options apart, leaving commas inside braces to the glob.
+fn words(head: &str) -> Vec<&str> {
+    let mut words = vec!();
+    let (mut depth, mut start) = (0, 0);
+    for (at, c) in head.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            c if depth == 0 && (c == ',' || c.is_whitespace()) => {
+                words.push(&head[start..at]);
+                start = at + c.len_utf8();
+            },
+            _   => (),
+        }
+    }
+    words.push(&head[start..]);
+    words.retain(|word| !word.is_empty());
+    words
+}
+
+/// A `[[rule]]` table, before its values are checked.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct RuleTable {
+    globs: Vec<String>,
+    script: String,
+    debounce: Option<String>,
+    on_busy: Option<String>,
+}
+
+impl TryFrom<RuleTable> for RuleSpec {
+    type Error = String;
+
+    fn try_from(table: RuleTable) -> Result<RuleSpec, String> {
+        if table.globs.is_empty() || table.script.is_empty() {
+            return Err("Rules need some globs and a script".to_string());
+        }
+
+        Ok(RuleSpec {
+            globs: table.globs,
+            debounce: table.debounce.as_deref().map(parse_duration).transpose()?,
+            on_busy: table.on_busy.as_deref().map(str::parse).transpose()?,
+            script: table.script,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use super::RuleSpec;
+    use super::super::policy::OnBusy;
+
+    #[test]
+    fn parsing() {
+        assert_eq!(Ok(RuleSpec {
+            globs: vec!("*.rs".to_string(), "Cargo.toml".to_string()),
+            debounce: None,
+            on_busy: None,
+            script: "cargo check".to_string(),
+        }), "*.rs,Cargo.toml: cargo check".parse());
+
+        assert_eq!(Ok(RuleSpec {
+            globs: vec!("migrations/**".to_string()),
+            debounce: Some(Duration::from_millis(500)),
+            on_busy: Some(OnBusy::Queue),
+            script: "diesel migration run && echo 'done: ok'".to_string(),
+        }), "migrations/** debounce=500ms on-busy=queue: diesel migration run && echo 'done: ok'".parse());
+
+        assert_eq!(Ok(RuleSpec {
+            globs: vec!("*.{rs,toml}".to_string(), "build.rs".to_string()),
+            debounce: None,
+            on_busy: None,
+            script: "cargo check".to_string(),
+        }), "*.{rs,toml},build.rs: cargo check".parse());
+
+        for bad in &["cargo check", ": cargo check", "*.rs:", "*.rs fast=yes: make", "*.rs on-busy=never: make"] {
+            assert!(bad.parse::<RuleSpec>().is_err(), "{} should be rejected", bad);
+        }
+    }
+}
@@ -0,0 +1,684 @@
+use os_pipe::{PipeWriter, pipe};
+use shared_child::SharedChild;
+use std::error::Error;
+use fwatch::{ Batch, Update, Watcher, };
+use std::ffi::OsString;
+use std::os::unix::process::CommandExt;
+use std::process::{ Command, ExitStatus, Stdio, };
+use std::io::{ BufRead, BufReader, };
+use std::sync::Arc;
+use std::sync::mpsc::{ channel, Sender, };
+use std::time::Instant;
+use super::notify::Notifications;
+use super::pager2::{ Pager2 };
+use super::policy::OnBusy;
+use super::process::{ watch_interrupts, Stopper, };
+use super::report::RunReport;
+use super::template::{ Context, Template, };
+
+/// Everything the event loop reacts to, funneled through one channel.
+enum Message {
+    Batch(Result<Batch, String>),
+    Warning(String),
+    /// Whatever the run with this id was running finished.
+    Exited(usize, Result<ExitStatus, String>),
+    PagerExit,
+    Interrupted,
+}
+
+/// One command to run, filled in from a batch.
+struct Step {
+    command: OsString,
+    template: Template,
+}
+
+impl Step {
+    fn new(template: &[String]) -> Result<Step, String> {
+        if template.is_empty() {
+            return Err("Empty template string!".to_string());
+        }
+
+        Ok(Step {
+            command: OsString::from(&template[0]),
+            template: Template::parse(&template[1..])?,
+        })
+    }
+
+    /// A script for `$SHELL -c`, falling back to `/bin/sh`.
+    fn shell(script: &[String]) -> Result<Step, String> {
+        if script.is_empty() {
+            return Err("Empty template string!".to_string());
+        }
+
+        Ok(Step {
+            command: std::env::var_os("SHELL")
+                .filter(|shell| !shell.is_empty())
+                .unwrap_or_else(|| OsString::from("/bin/sh")),
+            template: Template::parse_shell(script)?,
+        })
+    }
+
+    /// Construct a `Command` for the given batch.
+    ///
+    /// Placeholders are filled in from the most recently changed file, see
+    /// `Template`. The whole set of changed files is also exported newline
+    /// separated as `FWATCH_CHANGED`.
+    fn command(&self, batch: &Batch) -> Command {
+        let mut c = Command::new(&self.command);
+        if self.template.is_shell() {
+            c.arg("-c");
+        }
+        c.args(self.template.render(&Context {
+            change: &batch.latest,
+            changed: &batch.changed,
+        }));
+        c.env("FWATCH_CHANGED", batch.changed
+            .iter()
+            .map(|p| p.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("\n"));
+        c
+    }
+}
+
+/// Where a run is up to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Stage {
+    /// Running the step at this index.
+    Step(usize),
+    /// Every step is done and the success or failure hook is running.
+    Hook,
+}
+
+/// What to run for one rule's batches, and how.
+struct Job {
+    /// The command, then everything chained after it with `--then`.
+    steps: Vec<Step>,
+    on_success: Option<Step>,
+    on_failure: Option<Step>,
+    on_busy: OnBusy,
+    /// Changes waiting for a run to finish.
+    queued: Option<Batch>,
+}
+
+impl Job {
+    fn new(step: Step) -> Job {
+        Job {
+            steps: vec!(step),
+            on_success: None,
+            on_failure: None,
+            on_busy: OnBusy::Restart,
+            queued: None,
+        }
+    }
+}
+
+/// A batch working its way through the steps.
+struct Run {
+    id: usize,
+    child: Arc<SharedChild>,
+    stage: Stage,
+    batch: Batch,
+    started: Instant,
+}
+
+/// Runs a command for each batch of changes a `Watcher` reports.
+///
+/// Each of the watcher's rules has a job of its own, in the same order. Runs
+/// for one rule never stop or wait for another's.
+pub struct Runtime {
+    pager: Option<Arc<Pager2>>,
+    runs: Vec<Run>,
+    next_run: usize,
+    stopper: Stopper,
+    /// Where runs report that they finished, once the event loop is going.
+    exits: Option<Sender<Message>>,
+    jobs: Vec<Job>,
+    notifications: Notifications,
+    /// Extra environment for every command.
+    env: Vec<(String, String)>,
+}
+
+impl Runtime {
+    pub fn new(template: Vec<String>) -> Result<Runtime, String> {
+        Ok(Runtime::build(Step::new(&template)?))
+    }
+
+    /// Run the template as a script with `$SHELL -c`, falling back to `/bin/sh`.
+    pub fn new_shell(script: Vec<String>) -> Result<Runtime, String> {
+        Ok(Runtime::build(Step::shell(&script)?))
+    }
+
+    fn build(step: Step) -> Runtime {
+        Runtime {
+            pager: None,
+            runs: vec!(),
+            next_run: 0,
+            stopper: Stopper::default(),
+            exits: None,
+            jobs: vec!(Job::new(step)),
+            notifications: Notifications::default(),
+            env: vec!(),
+        }
+    }
+
+    pub fn use_pager(&mut self, should_page: bool) -> Result<&mut Runtime, Box<dyn Error>> {
+        self.pager = if should_page {
+            Some(Arc::new(Pager2::new()?))
+        }
+        else {
+            None
+        };
+        Ok(self)
+    }
+
+    /// Run a shell script for the watcher's next rule. `set_on_busy`,
+    /// `add_then`, `set_on_success` and `set_on_failure` configure the rule
+    /// added last.
+    pub fn add_rule(&mut self, script: &str) -> Result<&mut Runtime, String> {
+        self.jobs.push(Job::new(Step::shell(&[script.to_string()])?));
+        Ok(self)
+    }
+
+    fn job(&mut self) -> &mut Job {
+        self.jobs.last_mut().expect("there is always a job")
+    }
+
+    /// What to do with changes that arrive while the command is running.
+    pub fn set_on_busy(&mut self, on_busy: OnBusy) -> &mut Runtime {
+        self.job().on_busy = on_busy;
+        self
+    }
+
+    /// How to stop a run that is being replaced.
+    pub fn set_stopper(&mut self, stopper: Stopper) -> &mut Runtime {
+        self.stopper = stopper;
+        self
+    }
+
+    /// Chain a shell script to run once everything before it succeeded.
+    pub fn add_then(&mut self, script: &str) -> Result<&mut Runtime, String> {
+        self.job().steps.push(Step::shell(&[script.to_string()])?);
+        Ok(self)
+    }
+
+    /// A shell script to run after every step succeeded.
+    pub fn set_on_success(&mut self, script: &str) -> Result<&mut Runtime, String> {
+        self.job().on_success = Some(Step::shell(&[script.to_string()])?);
+        Ok(self)
+    }
+
+    /// A shell script to run after a step failed.
+    pub fn set_on_failure(&mut self, script: &str) -> Result<&mut Runtime, String> {
+        self.job().on_failure = Some(Step::shell(&[script.to_string()])?);
+        Ok(self)
+    }
+
+    /// How to notify the user when a run finishes.
+    pub fn set_notifications(&mut self, notifications: Notifications) -> &mut Runtime {
+        self.notifications = notifications;
+        self
+    }
+
+    /// Environment variables to set for every command.
+    pub fn set_env(&mut self, env: Vec<(String, String)>) -> &mut Runtime {
+        self.env = env;
+        self
+    }
+
+    /// Kick off the event loop.
+    pub fn run(mut self, watcher: Watcher) -> Result<(), Box<dyn Error>> {
+        let (tx, rx) = channel();
+        start_watcher(watcher, tx.clone());
+        self.exits = Some(tx.clone());
+        let interrupts = tx.clone();
+        watch_interrupts(move || { let _ = interrupts.send(Message::Interrupted); })?;
+
+        let output = match self.start_pager(tx) {
+            Some(pager) => Some(pipe_to_pager(pager)?),
+            None        => None,
+        };
+
+        for message in rx {
+            match message {
+                Message::Batch(Ok(batch))    => self.handle_batch(batch, output.as_ref()),
+                Message::Batch(Err(e))       => {
+                    self.stop_all();
+                    return Err(e.into());
+                },
+                Message::Warning(warning)    => self.warn(&warning),
+                Message::Exited(run, status) => self.exited(run, status, output.as_ref()),
+                Message::PagerExit           => break,
+                Message::Interrupted         => break,
+            }
+        }
+
+        self.stop_all();
+        Ok(())
+    }
+
+    fn start_pager(&self, tx: Sender<Message>) -> Option<Arc<Pager2>> {
+        if let Some(running_pager) = &self.pager {
+            let exit_monitor = running_pager.clone();
+            std::thread::spawn(move || {
+                exit_monitor.run();
+                let _ = tx.send(Message::PagerExit);
+            });
+            Some(running_pager.clone())
+        }
+        else {
+            None
+        }
+    }
+
+    /// Run the command for a batch, or hold on to it if that's what the policy
+    /// says to do while busy.
+    fn handle_batch(&mut self, batch: Batch, output: Option<&PipeWriter>) {
+        let rule = batch.rule;
+        let on_busy = self.jobs[rule].on_busy;
+        if self.running(rule) < on_busy.capacity() || on_busy == OnBusy::Restart {
+            self.run_batch(&batch, output);
+            return;
+        }
+
+        match on_busy {
+            OnBusy::Ignore => (),
+            _              => match &mut self.jobs[rule].queued {
+                Some(queued) => queued.merge(batch),
+                None         => self.jobs[rule].queued = Some(batch),
+            },
+        }
+    }
+
+    /// Move a run on to its next step or hook once the current one exits.
+    ///
+    /// The chain stops at the first step that fails. Runs that were stopped to
+    /// make way for another are already gone and go no further.
+    fn exited(&mut self, id: usize, status: Result<ExitStatus, String>, output: Option<&PipeWriter>) {
+        let index = match self.runs.iter().position(|run| run.id == id) {
+            Some(index) => index,
+            None        => return,
+        };
+        let status = match status {
+            Ok(status) => status,
+            Err(e)     => {
+                println!("Error waiting for command: {}", e);
+                self.finished(id, output);
+                return;
+            },
+        };
+
+        let run = &self.runs[index];
+        let job = &self.jobs[run.batch.rule];
+        let (stage, step, env) = match run.stage {
+            Stage::Step(step) if status.success() && step + 1 < job.steps.len() => {
+                (Stage::Step(step + 1), &job.steps[step + 1], vec!())
+            },
+            Stage::Step(_) => {
+                let report = RunReport {
+                    trigger: run.batch.latest.path.clone(),
+                    status,
+                    duration: run.started.elapsed(),
+                };
+                self.report(&report);
+                let hook = if report.success() { &job.on_success } else { &job.on_failure };
+                match hook {
+                    Some(hook) => (Stage::Hook, hook, report.env()),
+                    None       => return self.finished(id, output),
+                }
+            },
+            Stage::Hook => return self.finished(id, output),
+        };
+
+        match self.start(id, step, &run.batch, &env, output) {
+            Ok(child) => {
+                self.runs[index].child = child;
+                self.runs[index].stage = stage;
+            },
+            Err(e)    => {
+                println!("Error starting command: {}", e);
+                self.finished(id, output);
+            },
+        }
+    }
+
+    /// Show a warning from the watcher, in the pager when there is one so it
+    /// doesn't scribble over the screen.
+    fn warn(&self, warning: &str) {
+        match &self.pager {
+            Some(pager) => pager.add(&format!("Warning, {}\n", warning)),
+            None        => println!("Warning, {}", warning),
+        }
+    }
+
+    /// Tell the user how a run went.
+    fn report(&self, report: &RunReport) {
+        match &self.pager {
+            Some(pager) => pager.set_status(&report.to_string(), report.success()),
+            None        => println!("{}", report),
+        }
+        if let Err(e) = self.notifications.notify(report) {
+            println!("Warning, {}", e);
+        }
+    }
+
+    /// How many runs a rule has going.
+    fn running(&self, rule: usize) -> usize {
+        self.runs.iter().filter(|run| run.batch.rule == rule).count()
+    }
+
+    /// Forget a finished run, making room for anything its rule queued.
+    fn finished(&mut self, id: usize, output: Option<&PipeWriter>) {
+        let rule = match self.runs.iter().find(|run| run.id == id) {
+            Some(run) => run.batch.rule,
+            None      => return,
+        };
+        self.runs.retain(|run| run.id != id);
+        if self.running(rule) < self.jobs[rule].on_busy.capacity() {
+            if let Some(batch) = self.jobs[rule].queued.take() {
+                self.run_batch(&batch, output);
+            }
+        }
+    }
+
+    /// Stop every run, waiting for each process group to go away.
+    fn stop_all(&mut self) {
+        self.stop(|_| true);
+    }
+
+    /// Stop the runs that match, waiting for each process group to go away.
+    fn stop(&mut self, matches: impl Fn(&Run) -> bool) {
+        let (stopping, runs) = self.runs.drain(..).partition::<Vec<_>, _>(matches);
+        self.runs = runs;
+        for run in stopping {
+            if let Err(e) = self.stopper.stop(&run.child) {
+                println!("Error stopping command: {}", e);
+            }
+        }
+    }
+
+    // TODO: Replace output with customized pagers.
+    /// Run the first step for a batch, replacing any run of the same rule still
+    /// going when restarting.
+    fn run_batch(&mut self, batch: &Batch, output: Option<&PipeWriter>) {
+        if let Some(pager) = &self.pager {
+            pager.reset();
+        }
+
+        if self.jobs[batch.rule].on_busy == OnBusy::Restart {
+            self.stop(|run| run.batch.rule == batch.rule);
+        }
+
+        match self.start(self.next_run, &self.jobs[batch.rule].steps[0], batch, &[], output) {
+            Err(e)    => println!("Error starting command: {}", e),
+            Ok(child) => {
+                self.runs.push(Run {
+                    id: self.next_run,
+                    child,
+                    stage: Stage::Step(0),
+                    batch: batch.clone(),
+                    started: Instant::now(),
+                });
+                self.next_run += 1;
+            }
+        }
+    }
+
+    /// Start a step for the run with the given id, with some extra environment.
+    fn start(&self, id: usize, step: &Step, batch: &Batch, env: &[(&str, String)], output: Option<&PipeWriter>) -> Result<Arc<SharedChild>, String> {
+        let mut command = step.command(batch);
+        command.envs(self.env.iter().map(|(key, value)| (key, value)));
+        command.envs(env.iter().cloned());
+        if let Some(writer) = output {
+            let clone = || writer.try_clone().map_err(|e| format!("Error cloning output pipe: {}", e));
+            command.stdout(clone()?);
+            command.stderr(clone()?);
+        }
+        // Its own process group, so everything it starts can be stopped together.
+        // Being in the background, reading the terminal would stop it instead.
+        command.process_group(0);
+        command.stdin(Stdio::null());
+        let child = SharedChild::spawn(&mut command)
+            .map_err(|e| format!("Spawn error: {}", e))?;
+
+        let started = Arc::new(child);
+
+        let wait_clone = started.clone();
+        let exits = self.exits.clone();
+
+        std::thread::spawn(move || {
+            let status = wait_clone.wait().map_err(|e| e.to_string());
+            if let Some(exits) = exits {
+                let _ = exits.send(Message::Exited(id, status));
+            }
+        });
+
+        Ok(started)
+    }
+}
+
+/// Pump batches and warnings into the event loop from a background thread.
+fn start_watcher(watcher: Watcher, tx: Sender<Message>) {
+    std::thread::spawn(move || {
+        for update in watcher {
+            let message = match update {
+                Ok(Update::Batch(batch))     => Message::Batch(Ok(batch)),
+                Ok(Update::Warning(warning)) => Message::Warning(warning),
+                Err(e)                       => Message::Batch(Err(e)),
+            };
+            if tx.send(message).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+/// Forward everything written to the returned pipe into the pager.
+fn pipe_to_pager(pager: Arc<Pager2>) -> Result<PipeWriter, Box<dyn Error>> {
+    let (reader, writer) = pipe()?;
+    std::thread::spawn(move || {
+        let mut buffer = BufReader::new(reader);
+        loop {
+            let mut buf = String::new();
+            match buffer.read_line(&mut buf) {
+                Ok(0)  => { pager.add("EOF"); break; },
+                Ok(_)  => { pager.add(&buf); },
+                Err(e) => { pager.add(&format!("Error: {}", e)); break; },
+            };
+        };
+    });
+    Ok(writer)
+}
+
+#[cfg(test)]
+mod test {
+    use fwatch::{ Batch, ChangeEvent, ChangeKind, };
+    use super::super::policy::OnBusy;
+    use std::error::Error;
+    use os_pipe::pipe;
+    use std::io::Read;
+    use std::path::PathBuf;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+    use super::Message;
+
+    #[test]
+    fn construction() {
+        assert_eq!(super::Runtime::new(vec!()).err().unwrap(), "Empty template string!");
+        assert!(super::Runtime::new(vec!("echo", "{nope}").into_iter().map(str::to_string).collect()).is_err());
+        assert!(super::Runtime::new(vec!("echo", "{}").into_iter().map(str::to_string).collect()).is_ok());
+    }
+
+    #[test]
+    fn command_spawning() -> Result<(), Box<dyn Error>> {
+        let runtime = super::Runtime::new(vec!("echo",  "Test", "{}").into_iter().map(str::to_string).collect())?;
+        assert_eq!("Test Hello.txt\n", output_of(&runtime, &batch("Hello.txt"))?);
+        Ok(())
+    }
+
+    #[test]
+    fn no_terminal_input() -> Result<(), Box<dyn Error>> {
+        let runtime = super::Runtime::new(vec!("sh", "-c", "cat; echo done").into_iter().map(str::to_string).collect())?;
+        assert_eq!("done\n", output_of(&runtime, &batch("a.txt"))?);
+        Ok(())
+    }
+
+    #[test]
+    fn batch_placeholder() -> Result<(), Box<dyn Error>> {
+        let runtime = super::Runtime::new(vec!("echo", "{+}", "--", "{}").into_iter().map(str::to_string).collect())?;
+        let batch = Batch { changed: vec!(PathBuf::from("a.txt"), PathBuf::from("b.txt")), ..batch("b.txt") };
+        assert_eq!("a.txt b.txt -- b.txt\n", output_of(&runtime, &batch)?);
+        Ok(())
+    }
+
+    #[test]
+    fn changed_files_in_environment() -> Result<(), Box<dyn Error>> {
+        let runtime = super::Runtime::new(vec!("sh", "-c", "echo \"$FWATCH_CHANGED\"").into_iter().map(str::to_string).collect())?;
+        let batch = Batch { changed: vec!(PathBuf::from("a.txt"), PathBuf::from("b.txt")), ..batch("b.txt") };
+        assert_eq!("a.txt\nb.txt\n", output_of(&runtime, &batch)?);
+        Ok(())
+    }
+
+    #[test]
+    fn shell_mode() -> Result<(), Box<dyn Error>> {
+        let mut runtime = super::Runtime::new_shell(vec!("echo {} && echo {name} | tr a-z A-Z".to_string()))?;
+        runtime.jobs[0].steps[0].command = "/bin/sh".into();
+        assert_eq!("dir/with space.txt\nWITH SPACE.TXT\n", output_of(&runtime, &batch("dir/with space.txt"))?);
+        Ok(())
+    }
+
+    #[test]
+    fn relative_paths() -> Result<(), Box<dyn Error>> {
+        let runtime = super::Runtime::new(vec!("echo", "{event}", "{rel}").into_iter().map(str::to_string).collect())?;
+        let batch = Batch {
+            latest: ChangeEvent::new(ChangeKind::Modified, PathBuf::from("/repo/src/main.rs"), Some(PathBuf::from("/repo"))),
+            ..batch("/repo/src/main.rs")
+        };
+        assert_eq!("modified src/main.rs\n", output_of(&runtime, &batch)?);
+        Ok(())
+    }
+
+    #[test]
+    fn chained_steps() -> Result<(), Box<dyn Error>> {
+        let output = |then: &str| -> Result<String, Box<dyn Error>> {
+            let (mut reader, writer) = pipe()?;
+            let (tx, rx) = channel();
+            let mut runtime = super::Runtime::new(vec!("echo", "build {name}").into_iter().map(str::to_string).collect())?;
+            runtime.add_then(then)?
+                .set_on_success("echo ok $FWATCH_TRIGGER")?
+                .set_on_failure("echo failed with $FWATCH_EXIT_CODE")?;
+            let job = &mut runtime.jobs[0];
+            for step in job.steps.iter_mut().skip(1).chain(&mut job.on_success).chain(&mut job.on_failure) {
+                step.command = "/bin/sh".into();
+            }
+            runtime.exits = Some(tx);
+
+            runtime.handle_batch(batch("src/a.rs"), Some(&writer));
+            while !runtime.runs.is_empty() {
+                if let Message::Exited(id, status) = rx.recv_timeout(Duration::from_secs(5))? {
+                    runtime.exited(id, status, Some(&writer));
+                }
+            }
+            drop(writer);
+            let mut output = String::new();
+            reader.read_to_string(&mut output)?;
+            Ok(output)
+        };
+
+        assert_eq!("build a.rs\ntest src/a.rs\nok src/a.rs\n", output("echo test {}")?);
+        assert_eq!("build a.rs\nfailed with 3\n", output("exit 3; echo never")?);
+        Ok(())
+    }
+
+    /// Run the first job's command for a batch, returning what it printed.
+    fn output_of(runtime: &super::Runtime, batch: &Batch) -> Result<String, Box<dyn Error>> {
+        let (mut reader, writer) = pipe()?;
+        let tracker = runtime.start(0, &runtime.jobs[0].steps[0], batch, &[], Some(&writer))?;
+        drop(writer);
+        let mut output = String::new();
+        reader.read_to_string(&mut output)?;
+        assert!(tracker.wait()?.success());
+        Ok(output)
+    }
+
+    fn batch(path: &str) -> Batch {
+        Batch {
+            latest: ChangeEvent::new(ChangeKind::Modified, PathBuf::from(path), None),
+            changed: vec!(PathBuf::from(path)),
+            rule: 0,
+        }
+    }
+
+    fn running(runtime: &super::Runtime) -> Vec<usize> {
+        runtime.runs.iter().map(|run| run.id).collect()
+    }
+
+    fn kill_all(runtime: &super::Runtime) {
+        for run in &runtime.runs {
+            run.child.kill().unwrap();
+        }
+    }
+
+    #[test]
+    fn busy_policies() -> Result<(), Box<dyn Error>> {
+        let sleeper = || super::Runtime::new(vec!("sleep", "10").into_iter().map(str::to_string).collect());
+
+        let mut runtime = sleeper()?;
+        runtime.handle_batch(batch("a"), None);
+        let first = runtime.runs[0].child.clone();
+        runtime.handle_batch(batch("b"), None);
+        assert_eq!(vec!(1), running(&runtime));
+        assert!(!first.wait()?.success());
+        kill_all(&runtime);
+
+        let mut runtime = sleeper()?;
+        runtime.set_on_busy(OnBusy::Ignore);
+        runtime.handle_batch(batch("a"), None);
+        runtime.handle_batch(batch("b"), None);
+        assert_eq!(vec!(0), running(&runtime));
+        assert!(runtime.jobs[0].queued.is_none());
+        kill_all(&runtime);
+
+        let mut runtime = sleeper()?;
+        runtime.set_on_busy(OnBusy::Queue);
+        runtime.handle_batch(batch("a"), None);
+        runtime.handle_batch(batch("b"), None);
+        runtime.handle_batch(batch("c"), None);
+        assert_eq!(vec!(0), running(&runtime));
+        assert_eq!(vec!(PathBuf::from("b"), PathBuf::from("c")), runtime.jobs[0].queued.as_ref().unwrap().changed);
+        kill_all(&runtime);
+        runtime.finished(0, None);
+        assert_eq!(vec!(1), running(&runtime));
+        assert!(runtime.jobs[0].queued.is_none());
+        kill_all(&runtime);
+
+        let mut runtime = sleeper()?;
+        runtime.set_on_busy(OnBusy::Parallel(2));
+        runtime.handle_batch(batch("a"), None);
+        runtime.handle_batch(batch("b"), None);
+        runtime.handle_batch(batch("c"), None);
+        assert_eq!(vec!(0, 1), running(&runtime));
+        kill_all(&runtime);
+        runtime.finished(1, None);
+        assert_eq!(vec!(0, 2), running(&runtime));
+        kill_all(&runtime);
+        Ok(())
+    }
+
+    #[test]
+    fn separate_rules() -> Result<(), Box<dyn Error>> {
+        let mut runtime = super::Runtime::new(vec!("sleep", "10").into_iter().map(str::to_string).collect())?;
+        runtime.add_rule("sleep 10")?.set_on_busy(OnBusy::Queue);
+        let for_rule = |path, rule| Batch { rule, ..batch(path) };
+
+        runtime.handle_batch(for_rule("a", 0), None);
+        runtime.handle_batch(for_rule("b", 1), None);
+        runtime.handle_batch(for_rule("c", 0), None);
+        runtime.handle_batch(for_rule("d", 1), None);
+        assert_eq!(vec!(1, 2), running(&runtime));
+        assert!(runtime.jobs[0].queued.is_none());
+        assert_eq!(vec!(PathBuf::from("d")), runtime.jobs[1].queued.as_ref().unwrap().changed);
+        kill_all(&runtime);
+        Ok(())
+    }
+}
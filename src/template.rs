@@ -0,0 +1,279 @@
+use std::ffi::{ OsStr, OsString, };
+use std::os::unix::ffi::{ OsStrExt, OsStringExt, };
+use std::path::{ Path, PathBuf, };
+use fwatch::ChangeEvent;
+
+/// A piece of a template argument.
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    /// `{}`: the full path.
+    Path,
+    /// `{dir}`: the directory containing the file.
+    Dir,
+    /// `{name}`: the file name.
+    Name,
+    /// `{stem}`: the file name without its extension.
+    Stem,
+    /// `{ext}`: the extension, without the leading dot.
+    Ext,
+    /// `{rel}`: the path relative to the watched root it was found under.
+    Rel,
+    /// `{event}`: the kind of change.
+    Event,
+    /// `{+}` within a shell script: every changed path.
+    Batch,
+}
+
+#[derive(Debug, PartialEq)]
+enum Arg {
+    /// `{+}`: every changed path, one argument each.
+    Batch,
+    Parts(Vec<Segment>),
+}
+
+/// Everything a template can draw from when rendering.
+pub struct Context<'a> {
+    /// The change placeholders describe.
+    pub change: &'a ChangeEvent,
+    pub changed: &'a [PathBuf],
+}
+
+/// Parsed argument list of the command template.
+///
+/// Literal braces are written `{{` and `}}`.
+#[derive(Debug)]
+pub struct Template {
+    args: Vec<Arg>,
+    /// Whether this is a single shell script, with substitutions quoted.
+    shell: bool,
+}
+
+impl Template {
+    /// Parse template arguments, rejecting unknown placeholders.
+    pub fn parse(args: &[String]) -> Result<Template, String> {
+        Ok(Template {
+            args: args.iter()
+                .map(|arg| parse_arg(arg, false))
+                .collect::<Result<_, _>>()?,
+            shell: false,
+        })
+    }
+
+    /// Parse a shell script, joining the arguments with spaces.
+    ///
+    /// Substituted values are quoted for a POSIX shell, so paths with spaces or
+    /// quotes in them stay one word. `{+}` may appear anywhere and expands to
+    /// every changed path, each quoted.
+    pub fn parse_shell(args: &[String]) -> Result<Template, String> {
+        Ok(Template {
+            args: vec!(parse_arg(&args.join(" "), true)?),
+            shell: true,
+        })
+    }
+
+    pub fn is_shell(&self) -> bool {
+        self.shell
+    }
+
+    /// Substitute the placeholders for the given change.
+    pub fn render(&self, context: &Context) -> Vec<OsString> {
+        let mut rendered = vec!();
+        for arg in &self.args {
+            match arg {
+                Arg::Batch => {
+                    rendered.extend(context.changed.iter().map(|p| p.as_os_str().to_owned()));
+                },
+                Arg::Parts(parts) => {
+                    let mut value = OsString::new();
+                    for part in parts {
+                        render_segment(part, context, self.shell, &mut value);
+                    }
+                    rendered.push(value);
+                },
+            }
+        }
+        rendered
+    }
+}
+
+fn render_segment(segment: &Segment, context: &Context, shell: bool, value: &mut OsString) {
+    let path = context.change.path.as_path();
+    let substituted = match segment {
+        Segment::Literal(s) => {
+            value.push(s);
+            return;
+        },
+        Segment::Batch      => {
+            for (i, changed) in context.changed.iter().enumerate() {
+                if i > 0 {
+                    value.push(" ");
+                }
+                value.push(quote(changed.as_os_str()));
+            }
+            return;
+        },
+        Segment::Path       => path.as_os_str(),
+        Segment::Dir        => path.parent().unwrap_or_else(|| Path::new("")).as_os_str(),
+        Segment::Name       => path.file_name().unwrap_or_default(),
+        Segment::Stem       => path.file_stem().unwrap_or_default(),
+        Segment::Ext        => path.extension().unwrap_or_default(),
+        Segment::Rel        => context.change.root
+                                   .as_ref()
+                                   .and_then(|root| path.strip_prefix(root).ok())
+                                   .unwrap_or(path)
+                                   .as_os_str(),
+        Segment::Event      => {
+            value.push(context.change.kind.to_string());
+            return;
+        },
+    };
+
+    if shell {
+        value.push(quote(substituted));
+    }
+    else {
+        value.push(substituted);
+    }
+}
+
+/// Quote a value so a POSIX shell reads it back as exactly one word.
+fn quote(value: &OsStr) -> OsString {
+    let bytes = value.as_bytes();
+    let safe = |b: &u8| b.is_ascii_alphanumeric() || b"_@%+=:,./-".contains(b);
+    if !bytes.is_empty() && bytes.iter().all(safe) {
+        return value.to_owned();
+    }
+
+    let mut quoted = vec!(b'\'');
+    for b in bytes {
+        if *b == b'\'' {
+            quoted.extend_from_slice(b"'\\''");
+        }
+        else {
+            quoted.push(*b);
+        }
+    }
+    quoted.push(b'\'');
+    OsString::from_vec(quoted)
+}
+
+fn parse_arg(arg: &str, shell: bool) -> Result<Arg, String> {
+    if arg == "{+}" && !shell {
+        return Ok(Arg::Batch);
+    }
+
+    let mut parts = vec!();
+    let mut literal = String::new();
+    let mut chars = arg.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            },
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            },
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c)   => name.push(c),
+                        None      => return Err(format!("Unterminated placeholder in {:?}", arg)),
+                    }
+                }
+                if !literal.is_empty() {
+                    parts.push(Segment::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(parse_placeholder(&name, arg, shell)?);
+            },
+            '}' => return Err(format!("Unmatched '}}' in {:?}, use '}}}}' for a literal brace", arg)),
+            c => literal.push(c),
+        }
+    }
+
+    if !literal.is_empty() {
+        parts.push(Segment::Literal(literal));
+    }
+
+    Ok(Arg::Parts(parts))
+}
+
+fn parse_placeholder(name: &str, arg: &str, shell: bool) -> Result<Segment, String> {
+    match name {
+        ""      => Ok(Segment::Path),
+        "dir"   => Ok(Segment::Dir),
+        "name"  => Ok(Segment::Name),
+        "stem"  => Ok(Segment::Stem),
+        "ext"   => Ok(Segment::Ext),
+        "rel"   => Ok(Segment::Rel),
+        "event" => Ok(Segment::Event),
+        "+"     => if shell {
+            Ok(Segment::Batch)
+        }
+        else {
+            Err(format!("'{{+}}' must be an argument on its own, found in {:?}", arg))
+        },
+        other   => Err(format!("Unknown placeholder '{{{}}}' in {:?}", other, arg)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use super::{ Context, Template, };
+    use fwatch::{ ChangeEvent, ChangeKind, };
+
+    fn render(args: &[&str], path: &str) -> Vec<String> {
+        let template = Template::parse(&args.iter().map(|s| s.to_string()).collect::<Vec<_>>()).unwrap();
+        let changed = vec!(PathBuf::from(path), PathBuf::from("src/other.rs"));
+        let change = ChangeEvent::new(ChangeKind::Modified, PathBuf::from(path), Some(PathBuf::from("src")));
+        let context = Context {
+            change: &change,
+            changed: &changed,
+        };
+        template.render(&context)
+            .into_iter()
+            .map(|s| s.into_string().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(
+            render(&["{}", "{dir}", "{name}", "{stem}", "{ext}", "{rel}", "{event}"], "src/lib/mod.rs"),
+            vec!("src/lib/mod.rs", "src/lib", "mod.rs", "mod", "rs", "lib/mod.rs", "modified"));
+        assert_eq!(render(&["--out={stem}.o"], "src/a.c"), vec!("--out=a.o"));
+        assert_eq!(render(&["{+}"], "src/a.rs"), vec!("src/a.rs", "src/other.rs"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(render(&["{{}}", "{{{name}}}", "a}}b"], "src/a.rs"), vec!("{}", "{a.rs}", "a}b"));
+    }
+
+    #[test]
+    fn shell_scripts() {
+        let template = Template::parse_shell(&["cargo build &&".to_string(), "./run {} {name} -- {+}".to_string()]).unwrap();
+        assert!(template.is_shell());
+        let changed = vec!(PathBuf::from("src/it's here.rs"), PathBuf::from("src/plain.rs"));
+        let change = ChangeEvent::new(ChangeKind::Modified, changed[0].clone(), None);
+        let rendered = template.render(&Context {
+            change: &change,
+            changed: &changed,
+        });
+        assert_eq!(vec!("cargo build && ./run 'src/it'\\''s here.rs' 'it'\\''s here.rs' -- 'src/it'\\''s here.rs' src/plain.rs"),
+                   rendered.into_iter().map(|s| s.into_string().unwrap()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        for bad in &["{nope}", "{name", "a}b", "x{+}"] {
+            assert!(Template::parse(&[bad.to_string()]).is_err(), "{} should be rejected", bad);
+        }
+    }
+}
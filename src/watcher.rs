@@ -0,0 +1,565 @@
+use std::collections::VecDeque;
+use std::path::{ Path, PathBuf, };
+use std::sync::mpsc::{ channel, Receiver, RecvTimeoutError, };
+use std::time::Duration;
+use super::backend::{ Backend, FsEvent, };
+use super::backend::inotify::InotifyBackend;
+use super::change::{ ChangeEvent, ChangeKind, Translator, };
+use super::debounce::{ Batch, Debouncer, };
+use super::event::EventKind;
+use super::filter::Filter;
+use super::ignores::IgnoreFilter;
+use super::watches::{ WatchCount, WatchStats, };
+
+/// Events from one read of the backend, and any warnings it had along the way.
+type BackendRead = (Vec<FsEvent>, Vec<String>);
+
+/// Configures a `Watcher`.
+///
+/// ```no_run
+/// let mut builder = fwatch::WatcherBuilder::new();
+/// builder.root("src").debounce(std::time::Duration::from_millis(200));
+/// builder.filter().add_extension("rs");
+/// for update in builder.build()? {
+///     match update? {
+///         fwatch::Update::Batch(batch)     => println!("{} changed", batch.latest.path.display()),
+///         fwatch::Update::Warning(warning) => eprintln!("{}", warning),
+///     }
+/// }
+/// # Ok::<(), String>(())
+/// ```
+pub struct WatcherBuilder {
+    roots: Vec<PathBuf>,
+    rules: Vec<RuleConfig>,
+    respect_ignores: bool,
+    initial: bool,
+    backend: Option<Box<dyn Backend>>,
+}
+
+impl WatcherBuilder {
+    pub fn new() -> WatcherBuilder {
+        WatcherBuilder {
+            roots: vec!(),
+            rules: vec!(RuleConfig::new()),
+            respect_ignores: true,
+            initial: false,
+            backend: None,
+        }
+    }
+
+    /// Watch a directory tree, which `ChangeEvent::root` refers back to.
+    pub fn root(&mut self, path: impl AsRef<Path>) -> &mut WatcherBuilder {
+        self.roots.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Start another rule, which gets batches of its own. `filter`, `triggers`
+    /// and `debounce` configure the rule added last.
+    ///
+    /// ```no_run
+    /// let mut builder = fwatch::WatcherBuilder::new();
+    /// builder.root(".").filter().add_extension("rs");
+    /// builder.add_rule().filter().add_extension("scss");
+    /// for update in builder.build()? {
+    ///     if let fwatch::Update::Batch(batch) = update? {
+    ///         println!("rule {}: {} changed", batch.rule, batch.latest.path.display());
+    ///     }
+    /// }
+    /// # Ok::<(), String>(())
+    /// ```
+    pub fn add_rule(&mut self) -> &mut WatcherBuilder {
+        self.rules.push(RuleConfig::new());
+        self
+    }
+
+    fn rule(&mut self) -> &mut RuleConfig {
+        self.rules.last_mut().expect("there is always a rule")
+    }
+
+    /// Which changed files are reported.
+    pub fn filter(&mut self) -> &mut Filter {
+        &mut self.rule().filter
+    }
+
+    /// Which kinds of change are reported.
+    pub fn triggers(&mut self, triggers: Vec<EventKind>) -> &mut WatcherBuilder {
+        self.rule().triggers = triggers;
+        self
+    }
+
+    /// Whether `.gitignore`, `.ignore` and global git excludes hide changes.
+    /// A backend given to `backend` needs to be told separately.
+    pub fn respect_ignores(&mut self, respect: bool) -> &mut WatcherBuilder {
+        self.respect_ignores = respect;
+        self
+    }
+
+    /// Wait for the filesystem to be quiet for `window` before reporting a batch.
+    pub fn debounce(&mut self, window: Duration) -> &mut WatcherBuilder {
+        self.rule().debounce = window;
+        self
+    }
+
+    /// Report one batch of `Initial` changes for each rule, one per root, before
+    /// any real ones.
+    pub fn initial(&mut self, initial: bool) -> &mut WatcherBuilder {
+        self.initial = initial;
+        self
+    }
+
+    /// Replace the filesystem watcher, inotify by default.
+    pub fn backend(&mut self, backend: Box<dyn Backend>) -> &mut WatcherBuilder {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Watch every root and start listening for changes.
+    pub fn build(self) -> Result<Watcher, String> {
+        let mut backend = match self.backend {
+            Some(backend) => backend,
+            None          => Box::new(InotifyBackend::new(self.respect_ignores)?),
+        };
+
+        let mut stats = WatchStats::default();
+        for root in &self.roots {
+            let watched = backend.watch_root(root)?;
+            stats.requested += watched.requested;
+            stats.watched += watched.watched;
+        }
+        let watch_count = backend.watch_count();
+        let warnings = backend.take_warnings().into_iter().collect();
+
+        let (tx, rx) = channel();
+        std::thread::spawn(move || {
+            loop {
+                let events = backend.next_events()
+                    .map(|events| (events, backend.take_warnings()));
+                let failed = events.is_err();
+                if tx.send(events).is_err() || failed {
+                    return;
+                }
+            }
+        });
+
+        let roots = self.roots;
+        let initial = self.initial;
+        let rules = self.rules
+            .into_iter()
+            .map(|config| {
+                let mut debouncer = Debouncer::new(config.debounce);
+                if initial {
+                    for root in &roots {
+                        debouncer.add(ChangeEvent::new(ChangeKind::Initial, root.clone(), Some(root.clone())));
+                    }
+                }
+                Rule {
+                    filter: config.filter,
+                    triggers: config.triggers,
+                    debouncer,
+                }
+            })
+            .collect();
+
+        Ok(Watcher {
+            events: rx,
+            rules,
+            ignores: if self.respect_ignores { Some(IgnoreFilter::new()) } else { None },
+            translator: Translator::new(roots.clone()),
+            roots,
+            stats,
+            watch_count,
+            warnings,
+        })
+    }
+}
+
+impl Default for WatcherBuilder {
+    fn default() -> WatcherBuilder {
+        WatcherBuilder::new()
+    }
+}
+
+/// How one rule is set up, until it is built.
+struct RuleConfig {
+    filter: Filter,
+    triggers: Vec<EventKind>,
+    debounce: Duration,
+}
+
+impl RuleConfig {
+    fn new() -> RuleConfig {
+        RuleConfig {
+            filter: Filter::new(),
+            triggers: EventKind::defaults(),
+            debounce: Duration::from_millis(0),
+        }
+    }
+}
+
+/// Changes one rule is interested in, waiting for a quiet period.
+struct Rule {
+    filter: Filter,
+    triggers: Vec<EventKind>,
+    debouncer: Debouncer,
+}
+
+/// Filtered, debounced batches of changes under a set of roots.
+///
+/// Each rule filters and debounces the same changes separately, so one change
+/// can end up in a batch for each rule it matches.
+///
+/// Iterating blocks until the next batch or warning is ready, and ends if the
+/// backend stops. An `Err` is reported once, when the backend fails.
+pub struct Watcher {
+    events: Receiver<Result<BackendRead, String>>,
+    rules: Vec<Rule>,
+    ignores: Option<IgnoreFilter>,
+    translator: Translator,
+    roots: Vec<PathBuf>,
+    stats: WatchStats,
+    watch_count: Option<WatchCount>,
+    warnings: VecDeque<String>,
+}
+
+/// What iterating a `Watcher` produces.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Update {
+    Batch(Batch),
+    /// Something the watcher carried on past, like a directory it couldn't
+    /// watch or an ignore file it couldn't read.
+    Warning(String),
+}
+
+impl Watcher {
+    /// How many directories were found while walking, and how many of those
+    /// could be watched.
+    pub fn watch_stats(&self) -> WatchStats {
+        self.stats
+    }
+
+    /// How many watches are in use now. With inotify this follows directories
+    /// created and removed since startup, other backends keep what they watched
+    /// at startup.
+    pub fn watches_in_use(&self) -> usize {
+        self.watch_count
+            .as_ref()
+            .map(WatchCount::get)
+            .unwrap_or(self.stats.watched)
+    }
+
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    /// Find the most specific root a path was found under.
+    pub fn root_of(&self, path: &Path) -> Option<&Path> {
+        self.translator.root_of(path)
+    }
+
+    fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.ignores
+            .as_ref()
+            .map(|ignores| ignores.is_ignored(path, is_dir))
+            .unwrap_or(false)
+    }
+
+    /// Prune down to the changes a rule should report.
+    fn is_executable(&self, rule: usize, change: &ChangeEvent) -> bool {
+        if change.kind == ChangeKind::Rescan {
+            return true;
+        }
+
+        let rule = &self.rules[rule];
+        let triggered = change.kind.trigger()
+            .map(|kind| rule.triggers.contains(&kind))
+            .unwrap_or(false);
+
+        triggered
+            && !self.is_ignored(&change.path, false)
+            && rule.filter.matches(&change.path, change.root.as_deref())
+    }
+
+    /// What a rule should make of a change, if anything. A file renamed to
+    /// something the rule doesn't match has gone away as far as it's concerned.
+    fn change_for(&self, rule: usize, change: &ChangeEvent) -> Option<ChangeEvent> {
+        if self.is_executable(rule, change) {
+            return Some(change.clone());
+        }
+
+        if let ChangeKind::Renamed { from: Some(from) } = &change.kind {
+            let mut removed = ChangeEvent::new(ChangeKind::Removed, from.clone(), self.root_of(from).map(Path::to_path_buf));
+            removed.timestamp = change.timestamp;
+            if self.is_executable(rule, &removed) {
+                return Some(removed);
+            }
+        }
+        None
+    }
+
+    /// Queue up changed files for the next batch of every rule they match.
+    fn process_events(&mut self, events: &[FsEvent]) {
+        for change in self.translator.translate(events) {
+            if let Some(ignores) = &self.ignores {
+                ignores.notice_change(&change.path);
+            }
+
+            let matching = (0..self.rules.len())
+                .filter_map(|rule| self.change_for(rule, &change).map(|change| (rule, change)))
+                .collect::<Vec<_>>();
+            for (rule, change) in matching {
+                self.rules[rule].debouncer.add(change);
+            }
+        }
+
+        if let Some(ignores) = &self.ignores {
+            self.warnings.extend(ignores.take_warnings());
+        }
+    }
+}
+
+impl Iterator for Watcher {
+    type Item = Result<Update, String>;
+
+    fn next(&mut self) -> Option<Result<Update, String>> {
+        loop {
+            if let Some(warning) = self.warnings.pop_front() {
+                return Some(Ok(Update::Warning(warning)));
+            }
+            for (index, rule) in self.rules.iter_mut().enumerate() {
+                if let Some(mut batch) = rule.debouncer.take_ready() {
+                    batch.rule = index;
+                    return Some(Ok(Update::Batch(batch)));
+                }
+            }
+
+            let timeout = self.rules
+                .iter()
+                .filter_map(|rule| rule.debouncer.timeout())
+                .min();
+            let received = match timeout {
+                Some(timeout) => match self.events.recv_timeout(timeout) {
+                    Ok(events)                          => events,
+                    Err(RecvTimeoutError::Timeout)      => continue,
+                    Err(RecvTimeoutError::Disconnected) => return None,
+                },
+                None => self.events.recv().ok()?,
+            };
+
+            match received {
+                Ok((events, warnings)) => {
+                    self.warnings.extend(warnings);
+                    self.process_events(&events);
+                },
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::error::Error;
+    use std::path::PathBuf;
+    use std::time::{ Duration, Instant, };
+    use tempfile::{ tempdir, TempDir, };
+    use super::{ Update, Watcher, WatcherBuilder, };
+    use super::super::debounce::Batch;
+    use super::super::backend::{ FsEvent, FsEventKind, };
+    use super::super::backend::poll::PollBackend;
+    use super::super::change::{ ChangeEvent, ChangeKind, };
+
+    fn watcher(dir: &TempDir) -> Result<Watcher, String> {
+        let mut builder = WatcherBuilder::new();
+        builder.root(dir.path());
+        builder.build()
+    }
+
+    fn next_batch(watcher: &mut Watcher) -> Result<Batch, String> {
+        match watcher.next().unwrap()? {
+            Update::Batch(batch)     => Ok(batch),
+            Update::Warning(warning) => Err(warning),
+        }
+    }
+
+    #[test]
+    fn ignored_files() -> Result<(), Box<dyn Error>> {
+        let dir: TempDir = tempdir().unwrap();
+        std::fs::write(dir.path().join(".ignore"), "*.log\n")?;
+        let mut watcher = watcher(&dir)?;
+
+        let change = ChangeEvent::new(ChangeKind::Modified, dir.path().join("debug.log"), None);
+        assert!(!watcher.is_executable(0, &change));
+
+        watcher.ignores = None;
+        assert!(watcher.is_executable(0, &change));
+        Ok(())
+    }
+
+    #[test]
+    fn renames_and_removals() -> Result<(), Box<dyn Error>> {
+        let dir: TempDir = tempdir().unwrap();
+        let mut watcher = watcher(&dir)?;
+        let target = dir.path().join("a.txt");
+
+        watcher.process_events(&[
+            FsEvent::new(FsEventKind::MovedFrom(7), dir.path().join("a.tmp"), false),
+            FsEvent::new(FsEventKind::MovedTo(7), target.clone(), false),
+        ]);
+        let batch = watcher.rules[0].debouncer.take_ready().unwrap();
+        assert_eq!(ChangeKind::Renamed { from: Some(dir.path().join("a.tmp")) }, batch.latest.kind);
+        assert_eq!(vec!(target.clone()), batch.changed);
+        assert_eq!(Some(dir.path().to_path_buf()), batch.latest.root);
+
+        watcher.process_events(&[FsEvent::new(FsEventKind::Removed, target.clone(), false)]);
+        let batch = watcher.rules[0].debouncer.take_ready().unwrap();
+        assert_eq!((ChangeKind::Removed, target), (batch.latest.kind, batch.latest.path));
+
+        // Directories are the backend's business.
+        watcher.process_events(&[FsEvent::new(FsEventKind::Removed, dir.path().join("sub"), true)]);
+        assert!(watcher.rules[0].debouncer.take_ready().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn renamed_out_of_filter() -> Result<(), Box<dyn Error>> {
+        let dir: TempDir = tempdir().unwrap();
+        std::fs::write(dir.path().join(".ignore"), "*.log\n")?;
+        let mut builder = WatcherBuilder::new();
+        builder.root(dir.path()).filter().add_extension("rs");
+        let mut watcher = builder.build()?;
+        let source = dir.path().join("a.rs");
+
+        for (cookie, target) in [(1, "a.rs.bak"), (2, "a.log")].iter() {
+            watcher.process_events(&[
+                FsEvent::new(FsEventKind::MovedFrom(*cookie), source.clone(), false),
+                FsEvent::new(FsEventKind::MovedTo(*cookie), dir.path().join(target), false),
+            ]);
+            let batch = watcher.rules[0].debouncer.take_ready().unwrap();
+            assert_eq!((ChangeKind::Removed, source.clone()), (batch.latest.kind, batch.latest.path));
+        }
+
+        // Neither name matches.
+        watcher.process_events(&[
+            FsEvent::new(FsEventKind::MovedFrom(3), dir.path().join("a.txt"), false),
+            FsEvent::new(FsEventKind::MovedTo(3), dir.path().join("b.txt"), false),
+        ]);
+        assert!(watcher.rules[0].debouncer.take_ready().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn nested_roots() -> Result<(), Box<dyn Error>> {
+        let dir: TempDir = tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("src/nested"))?;
+        let mut builder = WatcherBuilder::new();
+        builder.root(dir.path()).root(dir.path().join("src/nested"));
+        let watcher = builder.build()?;
+        assert_eq!(3, watcher.watch_stats().watched);
+        assert_eq!(Some(dir.path().join("src/nested").as_path()),
+                   watcher.root_of(&dir.path().join("src/nested/a.rs")));
+        Ok(())
+    }
+
+    #[test]
+    fn watches_in_use() -> Result<(), Box<dyn Error>> {
+        let dir: TempDir = tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("a"))?;
+        let watcher = watcher(&dir)?;
+        assert_eq!(2, watcher.watches_in_use());
+
+        std::fs::create_dir(dir.path().join("b"))?;
+        std::fs::remove_dir(dir.path().join("a"))?;
+        std::fs::create_dir(dir.path().join("c"))?;
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while watcher.watches_in_use() != 3 {
+            assert!(Instant::now() < deadline, "watch count stuck at {}", watcher.watches_in_use());
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(2, watcher.watch_stats().watched);
+        Ok(())
+    }
+
+    #[test]
+    fn warnings() -> Result<(), Box<dyn Error>> {
+        let dir: TempDir = tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("root"))?;
+        let mut builder = WatcherBuilder::new();
+        builder.root(dir.path().join("root"));
+        let mut watcher = builder.build()?;
+
+        std::fs::rename(dir.path().join("root"), dir.path().join("moved"))?;
+        assert_eq!(Update::Warning(format!("watched directory {} was moved away", dir.path().join("root").display())),
+                   watcher.next().unwrap()?);
+        Ok(())
+    }
+
+    #[test]
+    fn rescan() -> Result<(), Box<dyn Error>> {
+        let dir: TempDir = tempdir().unwrap();
+        let mut watcher = watcher(&dir)?;
+
+        watcher.process_events(&[FsEvent::new(FsEventKind::Overflow, dir.path().to_path_buf(), true)]);
+        let batch = watcher.rules[0].debouncer.take_ready().unwrap();
+        assert_eq!((ChangeKind::Rescan, dir.path().to_path_buf()), (batch.latest.kind, batch.latest.path));
+        Ok(())
+    }
+
+    #[test]
+    fn rules() -> Result<(), Box<dyn Error>> {
+        let dir: TempDir = tempdir().unwrap();
+        let mut builder = WatcherBuilder::new();
+        builder.root(dir.path()).filter().add_extension("rs");
+        builder.add_rule().filter().add_extension("scss");
+        builder.add_rule().filter().add_include("**/*")?;
+        let mut watcher = builder.build()?;
+
+        watcher.process_events(&[
+            FsEvent::new(FsEventKind::Modified, dir.path().join("main.rs"), false),
+            FsEvent::new(FsEventKind::Modified, dir.path().join("site.scss"), false),
+        ]);
+        let batches = (0..3)
+            .map(|_| next_batch(&mut watcher).map(|batch| (batch.rule, batch.changed)))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(vec!(
+            (0, vec!(dir.path().join("main.rs"))),
+            (1, vec!(dir.path().join("site.scss"))),
+            (2, vec!(dir.path().join("main.rs"), dir.path().join("site.scss"))),
+        ), batches);
+        Ok(())
+    }
+
+    #[test]
+    fn initial_batch() -> Result<(), Box<dyn Error>> {
+        let dir: TempDir = tempdir().unwrap();
+        let mut builder = WatcherBuilder::new();
+        builder.root(dir.path()).initial(true);
+        let mut watcher = builder.build()?;
+
+        let batch = next_batch(&mut watcher)?;
+        assert_eq!(ChangeKind::Initial, batch.latest.kind);
+        assert_eq!(vec!(dir.path().to_path_buf()), batch.changed);
+        Ok(())
+    }
+
+    #[test]
+    fn batches() -> Result<(), Box<dyn Error>> {
+        let dir: TempDir = tempdir().unwrap();
+        let mut builder = WatcherBuilder::new();
+        builder.root(dir.path())
+            .debounce(Duration::from_millis(50))
+            .backend(Box::new(PollBackend::new(Duration::from_millis(10), true)));
+        builder.filter().add_extension("txt");
+        let mut watcher = builder.build()?;
+
+        std::fs::write(dir.path().join("skipped.log"), "hi")?;
+        std::fs::write(dir.path().join("a.txt"), "hi")?;
+        std::fs::write(dir.path().join("b.txt"), "hi")?;
+        let batch = next_batch(&mut watcher)?;
+        let changed = batch.changed
+            .iter()
+            .map(|path| path.strip_prefix(dir.path()).unwrap().to_path_buf())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!(PathBuf::from("a.txt"), PathBuf::from("b.txt")), changed);
+        Ok(())
+    }
+}
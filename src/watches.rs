@@ -0,0 +1,184 @@
+use inotify::WatchDescriptor;
+use std::collections::HashMap;
+use std::os::unix::fs::MetadataExt;
+use std::path::{ Path, PathBuf, };
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicUsize, Ordering, };
+
+/// Identifies a directory independent of the path used to reach it.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) struct FileId {
+    dev: u64,
+    ino: u64,
+}
+
+impl FileId {
+    /// Look up the directory a path points at, following symlinks.
+    pub fn of(path: &Path) -> std::io::Result<FileId> {
+        let meta = std::fs::metadata(path)?;
+        Ok(FileId {
+            dev: meta.dev(),
+            ino: meta.ino(),
+        })
+    }
+}
+
+/// How many directories were asked to be watched, and how many actually are.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WatchStats {
+    pub requested: usize,
+    pub watched: usize,
+}
+
+impl WatchStats {
+    pub fn missed(&self) -> usize {
+        self.requested - self.watched
+    }
+}
+
+/// How many watches a backend holds right now, readable from other threads.
+#[derive(Clone, Debug, Default)]
+pub struct WatchCount(Arc<AtomicUsize>);
+
+impl WatchCount {
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, count: usize) {
+        self.0.store(count, Ordering::Relaxed);
+    }
+}
+
+struct Watch {
+    path: PathBuf,
+    id: FileId,
+}
+
+struct Root {
+    path: PathBuf,
+    canonical: PathBuf,
+}
+
+/// Bookkeeping for every directory with an inotify watch, and the roots they
+/// were found under.
+///
+/// Directories are tracked by device and inode so that reaching the same
+/// directory twice, through overlapping roots or symlinks, only watches it once.
+pub(crate) struct WatchRegistry {
+    watches: HashMap<WatchDescriptor, Watch>,
+    ids: HashMap<FileId, WatchDescriptor>,
+    roots: Vec<Root>,
+    count: WatchCount,
+}
+
+impl WatchRegistry {
+    pub fn new() -> WatchRegistry {
+        WatchRegistry {
+            watches: HashMap::new(),
+            ids: HashMap::new(),
+            roots: vec!(),
+            count: WatchCount::default(),
+        }
+    }
+
+    /// Number of watches in use.
+    pub fn len(&self) -> usize {
+        self.watches.len()
+    }
+
+    /// Follows `len` as watches come and go.
+    pub fn count(&self) -> WatchCount {
+        self.count.clone()
+    }
+
+    pub fn path(&self, wd: &WatchDescriptor) -> Option<&Path> {
+        self.watches.get(wd).map(|watch| watch.path.as_path())
+    }
+
+    /// Whether the directory is watched under any name.
+    pub fn is_watched(&self, id: FileId) -> bool {
+        self.ids.contains_key(&id)
+    }
+
+    pub fn insert(&mut self, wd: WatchDescriptor, path: PathBuf, id: FileId) {
+        self.ids.insert(id, wd.clone());
+        self.watches.insert(wd, Watch { path, id });
+        self.count.set(self.len());
+    }
+
+    pub fn remove(&mut self, wd: &WatchDescriptor) -> Option<PathBuf> {
+        let watch = self.watches.remove(wd)?;
+        self.ids.remove(&watch.id);
+        self.count.set(self.len());
+        Some(watch.path)
+    }
+
+    /// Rename every watched directory under `from` to live under `to` instead.
+    pub fn repath(&mut self, from: &Path, to: &Path) {
+        for watch in self.watches.values_mut() {
+            if let Ok(rest) = watch.path.strip_prefix(from) {
+                watch.path = to.join(rest);
+            }
+        }
+    }
+
+    /// Watches for a directory and everything below it.
+    pub fn under(&self, root: &Path) -> Vec<WatchDescriptor> {
+        self.watches
+            .iter()
+            .filter(|(_, watch)| watch.path.starts_with(root))
+            .map(|(wd, _)| wd.clone())
+            .collect()
+    }
+
+    /// Record a root. Returns false if it is already covered by an existing
+    /// root, in which case there is nothing new to walk.
+    pub fn add_root(&mut self, path: &Path) -> Result<bool, String> {
+        let canonical = path.canonicalize()
+            .map_err(|e| format!("Can't watch {}: {}", path.display(), e))?;
+        let covered = self.roots
+            .iter()
+            .any(|root| canonical.starts_with(&root.canonical));
+
+        self.roots.push(Root {
+            path: path.to_path_buf(),
+            canonical,
+        });
+        Ok(!covered)
+    }
+
+    /// Watches whose directory is no longer at the recorded path.
+    pub fn missing(&self) -> Vec<WatchDescriptor> {
+        self.watches
+            .iter()
+            .filter(|(_, watch)| FileId::of(&watch.path).ok() != Some(watch.id))
+            .map(|(wd, _)| wd.clone())
+            .collect()
+    }
+
+    pub fn roots(&self) -> Vec<PathBuf> {
+        self.roots.iter().map(|root| root.path.clone()).collect()
+    }
+
+    pub fn is_root(&self, path: &Path) -> bool {
+        self.roots.iter().any(|root| root.path == path)
+    }
+}
+
+/// Explain why the kernel refused a watch, pointing at the sysctl when it's the
+/// per-user watch limit.
+pub(crate) fn describe_watch_error(path: &Path, e: &std::io::Error) -> String {
+    if e.raw_os_error() == Some(libc::ENOSPC) {
+        let limit = std::fs::read_to_string("/proc/sys/fs/inotify/max_user_watches")
+            .map(|limit| limit.trim().to_string())
+            .unwrap_or_else(|_| "unknown".to_string());
+        format!("hit the inotify watch limit (fs.inotify.max_user_watches = {}) at {}. \
+                 Raise it with `sysctl fs.inotify.max_user_watches=<higher>`, \
+                 changes in directories past the limit will be missed",
+                limit, path.display())
+    }
+    else {
+        format!("failed to add watch to {}: {}", path.display(), e)
+    }
+}